@@ -0,0 +1,118 @@
+use std::{collections::HashMap, io::Write, path::{Path, PathBuf}};
+
+use clio::Output;
+use log::{error, info, warn};
+use sqlite3::{Connection, State};
+
+use crate::prompt::Prompter;
+
+/// Every recorded file claimed by more than one installed package, with the packages claiming it in install order,
+/// so the last one is the package that was installed most recently
+pub fn overlaps(conn: &Connection) -> Result<Vec<(PathBuf, Vec<String>)>, sqlite3::Error> {
+    let mut owners: HashMap<PathBuf, Vec<String>> = HashMap::new();
+
+    let mut stmt = conn.prepare("SELECT package, installed FROM debs ORDER BY id")?;
+    while stmt.next()? == State::Row {
+        let package = stmt.read::<String>(0).unwrap_or_default();
+        let installed = stmt.read::<String>(1).unwrap_or_default();
+
+        for path in installed.split(',').filter(|s| !s.is_empty()) {
+            let packages = owners.entry(PathBuf::from(path.trim())).or_default();
+            // several rows of the same package don't make it owned twice, the latest one decides its place
+            packages.retain(|claimed| claimed != &package);
+            packages.push(package.clone());
+        }
+    }
+
+    // directories like /usr/bin are shared by design, recorded paths that are gone are still reported
+    let mut overlaps: Vec<(PathBuf, Vec<String>)> = owners.into_iter()
+        .filter(|(_, packages)| packages.len() > 1)
+        .filter(|(path, _)| !path.symlink_metadata().is_ok_and(|metadata| metadata.is_dir()))
+        .collect();
+    overlaps.sort();
+
+    Ok(overlaps)
+}
+
+/// Reports the paths that more than one package claims, e.g. after a forced overwrite, returns whether there were none.
+/// With `fix` only the most recently installed package keeps each of them, since its files are the ones on disk
+pub fn audit(conn: Connection, fix: bool, simulate: bool, prompter: Prompter, out: &mut Output) -> bool {
+    let overlaps = overlaps(&conn).expect("Failed to read installed files");
+
+    if overlaps.is_empty() {
+        info!("No file is claimed by more than one package");
+        return true;
+    }
+
+    for (path, packages) in &overlaps {
+        writeln!(out, "{}: {}", path.display(), packages.join(", ")).expect("Failed to write overlap");
+    }
+    warn!("{} file(s) are claimed by more than one package", overlaps.len());
+
+    if !fix {
+        return false;
+    }
+
+    if simulate {
+        for (path, packages) in &overlaps {
+            let (keeper, others) = packages.split_last().expect("overlaps have at least two packages");
+            info!("Would remove {} from {}, keeping it owned by {}", path.display(), others.join(", "), keeper);
+        }
+        return false;
+    }
+
+    if !prompter.confirm(&format!("Keep only the most recently installed owner of {} file(s)?", overlaps.len()), false) {
+        error!("Nothing was changed");
+        return false;
+    }
+
+    conn.execute("BEGIN").expect("Failed to begin transaction");
+    match disown(&conn, &overlaps) {
+        Ok(changed) => {
+            conn.execute("COMMIT").expect("Failed to commit transaction");
+            info!("Fixed {} file(s) in {} package row(s)", overlaps.len(), changed);
+            true
+        },
+        Err(e) => {
+            let _ = conn.execute("ROLLBACK");
+            error!("Failed to fix file ownership: {}, nothing was changed", e);
+            false
+        },
+    }
+}
+
+// removes every overlapping path from the rows of all but its most recently installed owner, returns how many rows changed
+fn disown(conn: &Connection, overlaps: &[(PathBuf, Vec<String>)]) -> Result<usize, sqlite3::Error> {
+    let keepers: HashMap<&Path, &str> = overlaps.iter()
+        .map(|(path, packages)| (path.as_path(), packages.last().expect("overlaps have at least two packages").as_str()))
+        .collect();
+
+    let mut rows = vec![];
+    let mut stmt = conn.prepare("SELECT id, package, installed FROM debs ORDER BY id")?;
+    while stmt.next()? == State::Row {
+        rows.push((stmt.read::<i64>(0)?, stmt.read::<String>(1).unwrap_or_default(), stmt.read::<String>(2).unwrap_or_default()));
+    }
+
+    let mut changed = 0;
+    for (id, package, installed) in rows {
+        let (kept, dropped): (Vec<&str>, Vec<&str>) = installed.split(',')
+            .filter(|s| !s.is_empty())
+            .partition(|path| keepers.get(Path::new(path.trim())).is_none_or(|keeper| *keeper == package));
+
+        if dropped.is_empty() {
+            continue;
+        }
+
+        for path in &dropped {
+            info!("{} no longer claims {}, {} does", package, path.trim(), keepers[Path::new(path.trim())]);
+        }
+
+        let mut update = conn.prepare("UPDATE debs SET installed = ? WHERE id = ?")?;
+        update.bind(1, kept.join(",").as_str())?;
+        update.bind(2, id)?;
+        update.next()?;
+        changed += 1;
+    }
+
+    Ok(changed)
+}
@@ -0,0 +1,130 @@
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use sqlite3::{Connection, State};
+
+/// Where a file that isn't debby's gets moved to before a package overwrites it
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".debby-orig");
+    PathBuf::from(backup)
+}
+
+/// Moves every path out of the way, they still have to be recorded once the package is in the database
+pub fn backup(paths: &[PathBuf]) -> Result<(), String> {
+    for path in paths {
+        let backup = backup_path(path);
+
+        std::fs::rename(path, &backup).map_err(|e| format!("Failed to back up {}: {}", path.display(), e))?;
+        info!("Backed up {} to {}", path.display(), backup.display());
+    }
+
+    Ok(())
+}
+
+/// Moves the backups of `paths` back in place, for an install that was cut short before it was recorded
+pub fn undo(paths: &[PathBuf]) {
+    for path in paths {
+        let backup = backup_path(path);
+
+        match std::fs::rename(&backup, path) {
+            Ok(()) => info!("Restored {}", path.display()),
+            Err(e) => warn!("Failed to restore {} from {}: {}", path.display(), backup.display(), e),
+        }
+    }
+}
+
+/// Remembers that installing the package in row `deb_id` moved `path` to its backup path
+pub fn record(conn: &Connection, deb_id: i64, path: &Path) -> Result<(), sqlite3::Error> {
+    let mut stmt = conn.prepare("INSERT INTO backups (deb_id, path, backup) VALUES (?, ?, ?)")?;
+    stmt.bind(1, deb_id)?;
+    stmt.bind(2, path.display().to_string().as_str())?;
+    stmt.bind(3, backup_path(path).display().to_string().as_str())?;
+    stmt.next()?;
+
+    Ok(())
+}
+
+/// The `(path, backup)` of every file installing one of the `deb_ids` rows moved out of the way
+pub fn backups(conn: &Connection, deb_ids: &[i64]) -> Result<Vec<(PathBuf, PathBuf)>, sqlite3::Error> {
+    let mut backups = vec![];
+
+    for &deb_id in deb_ids {
+        let mut stmt = conn.prepare("SELECT path, backup FROM backups WHERE deb_id = ? ORDER BY id")?;
+        stmt.bind(1, deb_id)?;
+
+        while stmt.next()? == State::Row {
+            backups.push((PathBuf::from(stmt.read::<String>(0)?), PathBuf::from(stmt.read::<String>(1)?)));
+        }
+    }
+
+    Ok(backups)
+}
+
+/// Hands the backups of row `from` over to row `to`, for when a reinstall replaces it
+pub fn reassign(conn: &Connection, from: i64, to: i64) -> Result<(), sqlite3::Error> {
+    let mut stmt = conn.prepare("UPDATE backups SET deb_id = ? WHERE deb_id = ?")?;
+    stmt.bind(1, to)?;
+    stmt.bind(2, from)?;
+    stmt.next()?;
+
+    Ok(())
+}
+
+/// Puts back the files the `deb_ids` rows overwrote, call it after their own files were removed
+pub fn restore(conn: &Connection, deb_ids: &[i64]) {
+    let backups = match backups(conn, deb_ids) {
+        Ok(backups) => backups,
+        Err(e) => {
+            warn!("Failed to look up backed up files: {}", e);
+            return;
+        }
+    };
+
+    for (path, backup) in &backups {
+        match std::fs::rename(backup, path) {
+            Ok(()) => info!("Restored {}", path.display()),
+            Err(e) => warn!("Failed to restore {} from {}: {}", path.display(), backup.display(), e),
+        }
+    }
+
+    for &deb_id in deb_ids {
+        let forgotten = conn.prepare("DELETE FROM backups WHERE deb_id = ?")
+            .and_then(|mut stmt| {
+                stmt.bind(1, deb_id)?;
+                stmt.next()
+            });
+        if let Err(e) = forgotten {
+            warn!("Failed to forget backed up files: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backups_are_recorded_and_restored() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let path = dir.path().join("conf");
+        std::fs::write(&path, "original").expect("Failed to write file");
+
+        let conn = sqlite3::open(":memory:").expect("Failed to open database");
+        crate::db::migrate(&conn).expect("Failed to migrate database");
+
+        backup(std::slice::from_ref(&path)).expect("Failed to back up");
+        std::fs::write(&path, "packaged").expect("Failed to overwrite file");
+        record(&conn, 1, &path).expect("Failed to record backup");
+        assert_eq!(backups(&conn, &[1]).expect("Failed to read backups"), [(path.clone(), backup_path(&path))]);
+        assert!(backups(&conn, &[2]).expect("Failed to read backups").is_empty());
+
+        // uninstalling removes the package's file first
+        std::fs::remove_file(&path).expect("Failed to remove file");
+        restore(&conn, &[1]);
+
+        assert_eq!(std::fs::read_to_string(&path).expect("Failed to read restored file"), "original");
+        assert!(!backup_path(&path).exists());
+        assert!(backups(&conn, &[1]).expect("Failed to read backups").is_empty());
+    }
+}
@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use sqlite3::{Connection, Error, State};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 macro_rules! fielded_struct {
     (
@@ -42,8 +42,13 @@ macro_rules! fielded_struct {
                 (cols.join(", "), vals.join(", "))
             }
 
+            /// Every field with its unquoted value, in declaration order
+            pub fn values(&self) -> Vec<(&'static str, Option<String>)> {
+                vec![$((stringify!($fname), self.$fname.raw_value())),*]
+            }
+
             pub fn field(&self, field_name: &str) -> Option<String> {
-                match field_name {
+                match normalize_field_name(field_name).as_str() {
                     $(
                         stringify!($fname) => Some(format_field(&self.$fname)),
                     )*
@@ -54,6 +59,32 @@ macro_rules! fielded_struct {
     };
 }
 
+/// Why `ControlWithData::from_db` couldn't find a package
+#[derive(Debug)]
+pub enum LookupError {
+    /// No version of the package is installed
+    NotInstalled,
+    /// Another version of the package is installed (the latest one if there are several)
+    VersionMismatch { installed: String },
+    Db(Error),
+}
+
+impl From<Error> for LookupError {
+    fn from(e: Error) -> Self {
+        LookupError::Db(e)
+    }
+}
+
+impl std::fmt::Display for LookupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LookupError::NotInstalled => write!(f, "package is not installed"),
+            LookupError::VersionMismatch { installed } => write!(f, "a different version of the package is installed ({})", installed),
+            LookupError::Db(e) => write!(f, "{}", e.message.as_deref().unwrap_or("unknown database error")),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ControlWithData {
     pub ctrl: Control,
@@ -61,16 +92,51 @@ pub struct ControlWithData {
 }
 
 impl ControlWithData {
-    pub fn from_db(conn: &Connection, package_name: &str, version: &str) -> Result<Self, Error> {
+    pub fn from_db(conn: &Connection, package_name: &str, version: &str) -> Result<Self, LookupError> {
+        // look the package up by name first so a missing package and a different version can be told apart
+        let mut versions = conn.prepare("SELECT version FROM debs WHERE lower(package) = ? ORDER BY id DESC")?;
+        versions.bind(1, normalize_package_name(package_name).as_str())?;
+
+        let mut latest = None;
+        let mut found = false;
+        while versions.next()? == State::Row {
+            let installed = versions.read::<String>(0)?;
+            found |= installed == version;
+            latest.get_or_insert(installed);
+        }
+
+        match latest {
+            None => return Err(LookupError::NotInstalled),
+            Some(installed) if !found => return Err(LookupError::VersionMismatch { installed }),
+            Some(_) => {},
+        }
+
         let query = format!(
-            "SELECT {} FROM debs WHERE package = ? AND version = ?",
+            "SELECT {} FROM debs WHERE lower(package) = ? AND version = ?",
             Control::sql_fields() + ", installed"
         );
 
         let mut stmt = conn.prepare(&query)?;
-        stmt.bind(1, package_name)?;
+        stmt.bind(1, normalize_package_name(package_name).as_str())?;
         stmt.bind(2, version)?;
 
+        Ok(Self::read_row(stmt)?)
+    }
+
+    /// The most recently installed version of a package, whatever version that is
+    pub fn latest(conn: &Connection, package_name: &str) -> Result<Self, Error> {
+        let query = format!(
+            "SELECT {} FROM debs WHERE lower(package) = ? ORDER BY id DESC LIMIT 1",
+            Control::sql_fields() + ", installed"
+        );
+
+        let mut stmt = conn.prepare(&query)?;
+        stmt.bind(1, normalize_package_name(package_name).as_str())?;
+
+        Self::read_row(stmt)
+    }
+
+    fn read_row(mut stmt: sqlite3::Statement) -> Result<Self, Error> {
         if stmt.next()? == State::Row {
             let mut map = HashMap::new();
 
@@ -85,10 +151,8 @@ impl ControlWithData {
             modified_map.remove("installed");
             modified_map.remove("id");
 
-            let installed = match map.get("installed") {
-                Some(installed) => installed.to_string(),
-                None => return Err(sqlite3::Error{code: None, message: Some("Could not find 'installed' field".to_string())})
-            };
+            // NULL when no files were recorded
+            let installed = map.get("installed").cloned().unwrap_or_default();
 
             let ctrl = match from_map(modified_map) {
                 Ok(ctrl) => ctrl,
@@ -143,39 +207,184 @@ impl SqlFormat for i64 {
     }
 }
 
+// the value as it appears in the control file
+trait RawValue {
+    fn raw_value(&self) -> Option<String>;
+}
+
+impl RawValue for String {
+    fn raw_value(&self) -> Option<String> {
+        Some(self.clone())
+    }
+}
+
+impl RawValue for Option<String> {
+    fn raw_value(&self) -> Option<String> {
+        self.clone()
+    }
+}
+
 fn format_field<T: SqlFormat>(field: &T) -> String {
     field.format_sql()
 }
 
+/// Quotes a value for a statement the way the control fields are, `None` is NULL
+pub fn sql_value(value: Option<String>) -> String {
+    value.format_sql()
+}
+
+// `CURL`, `curl:amd64` and `curl` all refer to the same package
+pub fn normalize_package_name(name: &str) -> String {
+    let name = name.trim();
+    name.split_once(':').map_or(name, |(name, _arch)| name).to_lowercase()
+}
+
+// `Installed-Size` and `installed_size` both refer to the same field
+pub fn normalize_field_name(name: &str) -> String {
+    name.trim().to_lowercase().replace('-', "_")
+}
+
+// `installed_size` is spelled `Installed-Size` in control files
+pub fn control_field_name(name: &str) -> String {
+    name.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars.next().map_or(String::new(), |first| first.to_uppercase().chain(chars).collect())
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Renders a control paragraph in deb822 form, continuation lines are indented by a space and blank lines become ` .`
+pub fn to_deb822(ctrl: &Control) -> String {
+    let mut out = String::new();
+
+    for (name, value) in ctrl.values() {
+        let Some(value) = value.filter(|value| !value.is_empty()) else { continue };
+        let mut lines = value.lines();
+
+        out.push_str(&format!("{}: {}\n", control_field_name(name), lines.next().unwrap_or("")));
+        for line in lines {
+            let line = if line.trim().is_empty() { "." } else { line };
+            out.push_str(&format!(" {}\n", line));
+        }
+    }
+
+    out
+}
+
 fielded_struct! {
-    #[derive(Debug, Deserialize, PartialEq, Eq, Clone)]
+    // serializes with the field names of control files, deserializing takes those or the lowercase ones
+    #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
     pub struct Control {
+        #[serde(rename(serialize = "Package"), alias = "Package")]
         pub package: String,
+		#[serde(rename(serialize = "Version"), alias = "Version")]
 		pub version: String,
+		#[serde(rename(serialize = "Architecture"), alias = "Architecture")]
 		pub architecture: String,
+		#[serde(rename(serialize = "Maintainer"), alias = "Maintainer")]
 		pub maintainer: String,
-        #[serde(default)]
+		#[serde(rename(serialize = "Description"), alias = "Description", default)]
 		pub description: String,
 
+		#[serde(rename(serialize = "Depends"), alias = "Depends", skip_serializing_if = "Option::is_none")]
 		pub depends: Option<String>,
+		#[serde(rename(serialize = "Pre-Depends"), alias = "Pre-Depends", skip_serializing_if = "Option::is_none")]
 		pub pre_depends: Option<String>,
+		#[serde(rename(serialize = "Provides"), alias = "Provides", skip_serializing_if = "Option::is_none")]
 		pub provides: Option<String>,
+		#[serde(rename(serialize = "Section"), alias = "Section", skip_serializing_if = "Option::is_none")]
 		pub section: Option<String>,
+		#[serde(rename(serialize = "Priority"), alias = "Priority", skip_serializing_if = "Option::is_none")]
 		pub priority: Option<String>,
+		#[serde(rename(serialize = "Installed-Size"), alias = "Installed-Size", skip_serializing_if = "Option::is_none")]
 		pub installed_size: Option<String>,
+		#[serde(rename(serialize = "Recommends"), alias = "Recommends", skip_serializing_if = "Option::is_none")]
 		pub recommends: Option<String>,
+		#[serde(rename(serialize = "Suggests"), alias = "Suggests", skip_serializing_if = "Option::is_none")]
 		pub suggests: Option<String>,
+		#[serde(rename(serialize = "Enhances"), alias = "Enhances", skip_serializing_if = "Option::is_none")]
 		pub enhances: Option<String>,
+		#[serde(rename(serialize = "Breaks"), alias = "Breaks", skip_serializing_if = "Option::is_none")]
 		pub breaks: Option<String>,
+		#[serde(rename(serialize = "Conflicts"), alias = "Conflicts", skip_serializing_if = "Option::is_none")]
 		pub conflicts: Option<String>,
+		#[serde(rename(serialize = "Replaces"), alias = "Replaces", skip_serializing_if = "Option::is_none")]
 		pub replaces: Option<String>,
+		#[serde(rename(serialize = "Bugs"), alias = "Bugs", skip_serializing_if = "Option::is_none")]
 		pub bugs: Option<String>,
+		#[serde(rename(serialize = "License"), alias = "License", skip_serializing_if = "Option::is_none")]
 		pub license: Option<String>,
+		#[serde(rename(serialize = "Homepage"), alias = "Homepage", skip_serializing_if = "Option::is_none")]
 		pub homepage: Option<String>,
+		#[serde(rename(serialize = "Origin"), alias = "Origin", skip_serializing_if = "Option::is_none")]
 		pub origin: Option<String>
     }
 }
 
+impl Control {
+    /// The first line of the description, e.g. what `all` shows
+    pub fn synopsis(&self) -> String {
+        split_description(&self.description).0
+    }
+
+    /// The description without its synopsis, `None` if there's nothing past the first line
+    pub fn long_description(&self) -> Option<String> {
+        split_description(&self.description).1
+    }
+
+    /// Splits a `Name <email>` maintainer into its name and email, name-only maintainers have no email
+    pub fn maintainer_parts(&self) -> (String, Option<String>) {
+        match self.maintainer.split_once('<') {
+            Some((name, email)) => (name.trim().to_string(), Some(email.trim().trim_end_matches('>').trim().to_string())),
+            None => (self.maintainer.trim().to_string(), None),
+        }
+    }
+
+    /// What's wrong with the maintainer field, if anything. It's only ever worth a warning
+    pub fn maintainer_problem(&self) -> Option<String> {
+        let (name, email) = self.maintainer_parts();
+
+        if name.is_empty() {
+            return Some("no name".to_string());
+        }
+        if self.maintainer.contains('<') && !self.maintainer.trim_end().ends_with('>') {
+            return Some("the email isn't closed with '>'".to_string());
+        }
+
+        let email = email?;
+        let valid = match email.split_once('@') {
+            Some((user, domain)) => !user.is_empty() && domain.contains('.') && !domain.starts_with('.') && !domain.ends_with('.')
+                && !email.contains(char::is_whitespace) && !domain.contains('@'),
+            None => false,
+        };
+
+        (!valid).then(|| format!("{:?} isn't a valid email address", email))
+    }
+
+    /// How `Installed-Size` (in KiB) is off from the `data_bytes` the `entries` of the data archive add up to, if it's
+    /// off by more than 10% (or 16 KiB for tiny packages). dpkg rounds every entry up to a whole KiB, so up to one KiB
+    /// per entry more is fine too
+    pub fn installed_size_problem(&self, data_bytes: u64, entries: usize) -> Option<String> {
+        let declared = self.installed_size.as_deref()?.trim();
+        let Ok(declared) = declared.parse::<u64>() else {
+            return Some(format!("{:?} isn't a size in KiB", declared));
+        };
+
+        let actual = data_bytes.div_ceil(1024);
+        let tolerance = (actual / 10).max(16);
+
+        if declared + tolerance < actual {
+            Some(format!("it says {} KiB but the files add up to {} KiB", declared, actual))
+        } else if declared > actual + entries as u64 + tolerance {
+            Some(format!("it says {} KiB but the files only add up to {} KiB", declared, actual))
+        } else {
+            None
+        }
+    }
+}
+
 pub fn parse_control(control: String) -> Result<Control, serde_json::Error> {
     let lines = control.lines().collect::<Vec<_>>();
     let mut kvs: HashMap<String, String> = HashMap::new();
@@ -184,11 +393,11 @@ pub fn parse_control(control: String) -> Result<Control, serde_json::Error> {
     for line in lines {
         if line.starts_with(' ') || line.starts_with('\t') {
             // Continuation line - append to current value
-            if let Some(key) = &current_key {
-                if let Some(val) = kvs.get_mut(key) {
-                    val.push('\n');
-                    val.push_str(line.trim());
-                }
+            if let Some(key) = &current_key
+                && let Some(val) = kvs.get_mut(key)
+            {
+                val.push('\n');
+                val.push_str(line.trim());
             }
         } else if let Some((key, value)) = line.split_once(':') {
             // New key-value pair
@@ -201,10 +410,220 @@ pub fn parse_control(control: String) -> Result<Control, serde_json::Error> {
     from_map(kvs)
 }
 
+/// Splits a description into its synopsis and the extended description after it. Continuation lines were
+/// already unfolded by `parse_control`, the ones that are just ` .` stand for blank lines
+pub fn split_description(description: &str) -> (String, Option<String>) {
+    let mut lines = description.lines();
+    let synopsis = lines.next().unwrap_or("").trim().to_string();
+
+    let long = lines
+        .map(|line| if line.trim() == "." { "" } else { line })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    (synopsis, (!long.trim().is_empty()).then_some(long))
+}
+
+/// Checks a control file against the deb822 rules `parse_control` lets slide, the error comes with its 1-based line number
+pub fn check_deb822(control: &str) -> Result<(), (usize, String)> {
+    let mut seen: Vec<String> = vec![];
+    let mut paragraph_ended = false;
+
+    for (i, line) in control.lines().enumerate() {
+        let line_no = i + 1;
+
+        if line.trim().is_empty() {
+            paragraph_ended = !seen.is_empty();
+            continue;
+        }
+        if paragraph_ended {
+            return Err((line_no, "a control file has a single paragraph, but there's more after a blank line".to_string()));
+        }
+
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if seen.is_empty() {
+                return Err((line_no, "continuation line with no field before it".to_string()));
+            }
+            continue;
+        }
+
+        let Some((key, _)) = line.split_once(':') else {
+            return Err((line_no, format!("{:?} isn't a `Field: value` line", line)));
+        };
+        if key.is_empty() || key.starts_with(['#', '-']) || key.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err((line_no, format!("{:?} isn't a valid field name", key)));
+        }
+
+        // field names are case insensitive
+        if seen.contains(&key.to_lowercase()) {
+            return Err((line_no, format!("duplicate field {}", key)));
+        }
+        seen.push(key.to_lowercase());
+    }
+
+    Ok(())
+}
+
 pub fn from_map(map: HashMap<String, String>) -> Result<Control, serde_json::Error> {
     serde_json::from_value(serde_json::Value::Object(
         map.into_iter()
-            .map(|(k, v)| (k.to_lowercase(), v.into()))
+            .map(|(k, v)| (normalize_field_name(&k), v.into()))
             .collect()
     ))
 }
+
+/// Parses a `Provides` field into `(package, version)` pairs, e.g. `foo, bar (= 1.0)`
+pub fn parse_provides(s: &str) -> Vec<(String, Option<String>)> {
+    s.split(',')
+        .filter_map(|entry| {
+            let (name, version) = match entry.split_once('(') {
+                Some((name, rest)) => {
+                    let version = rest.trim_end().trim_end_matches(')').trim_start_matches([' ', '=']).trim();
+                    (name, (!version.is_empty()).then(|| version.to_string()))
+                },
+                None => (entry, None),
+            };
+
+            let name = name.trim().split(':').next().unwrap_or("").to_string();
+            (!name.is_empty()).then_some((name, version))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTROL: &str = "Package: hello\nVersion: 1.0-1\nArchitecture: amd64\nMaintainer: Jane Doe <jane@example.org>\nInstalled-Size: 42\nPre-Depends: libc6 (>= 2.34)\nDescription: says hello\n";
+
+    fn control() -> Control {
+        parse_control(CONTROL.to_string()).expect("Failed to parse control")
+    }
+
+    // an in-memory database with `control` installed under the given package names and versions
+    fn db(installed: &[(&str, &str)]) -> Connection {
+        let conn = sqlite3::open(":memory:").expect("Failed to open database");
+        crate::db::migrate(&conn).expect("Failed to migrate database");
+
+        for (package, version) in installed {
+            let ctrl = Control { package: package.to_string(), version: version.to_string(), ..control() };
+            let (cols, vals) = ctrl.populate_sql();
+            conn.execute(format!("INSERT INTO debs ({}, installed) VALUES ({}, '/usr/bin/{}')", cols, vals, package))
+                .expect("Failed to insert package");
+        }
+
+        conn
+    }
+
+    #[test]
+    fn field_names_are_normalized() {
+        assert_eq!(normalize_field_name("Installed-Size"), "installed_size");
+        assert_eq!(normalize_field_name(" installed-size "), "installed_size");
+        assert_eq!(normalize_field_name("VERSION"), "version");
+        assert_eq!(normalize_field_name("Pre-Depends"), "pre_depends");
+    }
+
+    #[test]
+    fn field_is_case_insensitive() {
+        let ctrl = control();
+
+        for name in ["Installed-Size", "installed_size", "INSTALLED-SIZE"] {
+            assert_eq!(ctrl.field(name).as_deref(), Some("'42'"), "{}", name);
+        }
+        for name in ["Version", "version", "VERSION"] {
+            assert_eq!(ctrl.field(name).as_deref(), Some("'1.0-1'"), "{}", name);
+        }
+        for name in ["Pre-Depends", "pre_depends", "pre-depends"] {
+            assert_eq!(ctrl.field(name).as_deref(), Some("'libc6 (>= 2.34)'"), "{}", name);
+        }
+        assert_eq!(ctrl.field("No-Such-Field"), None);
+    }
+
+    #[test]
+    fn package_names_are_normalized() {
+        assert_eq!(normalize_package_name("curl"), "curl");
+        assert_eq!(normalize_package_name("CURL"), "curl");
+        assert_eq!(normalize_package_name("curl:amd64"), "curl");
+        assert_eq!(normalize_package_name(" Curl:AMD64 "), "curl");
+    }
+
+    #[test]
+    fn lookups_ignore_case_and_architecture() {
+        let conn = db(&[("curl", "8.0")]);
+
+        for name in ["curl", "CURL", "curl:amd64", "Curl:AMD64"] {
+            let cwd = ControlWithData::from_db(&conn, name, "8.0").unwrap_or_else(|e| panic!("{}: {:?}", name, e));
+            assert_eq!(cwd.ctrl.package, "curl");
+            assert_eq!(ControlWithData::latest(&conn, name).expect("Failed to look up latest").ctrl.package, "curl");
+        }
+    }
+
+    #[test]
+    fn control_without_trailing_newline_keeps_its_last_line() {
+        let control = "Package: hello\nVersion: 1.0\nArchitecture: all\nMaintainer: Jane Doe\nDescription: says hello\n a longer\n .\n last line";
+
+        let ctrl = parse_control(control.to_string()).expect("Failed to parse control");
+        assert_eq!(ctrl.description, "says hello\na longer\n.\nlast line");
+    }
+
+    #[test]
+    fn from_db_tells_missing_packages_and_other_versions_apart() {
+        let conn = db(&[("curl", "7.0"), ("curl", "8.0")]);
+
+        assert!(matches!(ControlWithData::from_db(&conn, "wget", "1.0"), Err(LookupError::NotInstalled)));
+        assert!(matches!(
+            ControlWithData::from_db(&conn, "curl", "9.0"),
+            Err(LookupError::VersionMismatch { installed }) if installed == "8.0"
+        ));
+        assert_eq!(ControlWithData::from_db(&conn, "curl", "7.0").expect("Failed to look up curl 7.0").ctrl.version, "7.0");
+    }
+
+    #[test]
+    fn maintainer_is_split_into_name_and_email() {
+        let maintainer = |maintainer: &str| Control { maintainer: maintainer.to_string(), ..control() }.maintainer_parts();
+
+        assert_eq!(maintainer("Jane Doe <jane@example.org>"), ("Jane Doe".to_string(), Some("jane@example.org".to_string())));
+        assert_eq!(maintainer("Jane Doe"), ("Jane Doe".to_string(), None));
+        assert_eq!(maintainer("  Jane Doe   <  jane@example.org >  "), ("Jane Doe".to_string(), Some("jane@example.org".to_string())));
+        assert_eq!(maintainer("  Jane Doe  "), ("Jane Doe".to_string(), None));
+    }
+
+    #[test]
+    fn deb822_problems_report_their_line() {
+        assert_eq!(check_deb822(CONTROL), Ok(()));
+
+        let line = |control: &str| check_deb822(control).map_err(|(line, _)| line);
+        assert_eq!(line(" leading continuation\nPackage: hello\n"), Err(1));
+        assert_eq!(line("Package: hello\nVersion: 1.0\nno colon here\n"), Err(3));
+        assert_eq!(line("Package: hello\nDescription: hi\n more\nPACKAGE: again\n"), Err(4));
+        assert_eq!(line("Package: hello\n\nVersion: 1.0\n"), Err(3));
+    }
+
+    #[test]
+    fn serde_round_trips_with_control_field_names() {
+        let ctrl = control();
+
+        let json = serde_json::to_value(&ctrl).expect("Failed to serialize control");
+        assert_eq!(json["Package"], "hello");
+        assert_eq!(json["Installed-Size"], "42");
+        assert_eq!(json["Pre-Depends"], "libc6 (>= 2.34)");
+        assert!(json.get("package").is_none());
+        assert!(json.get("Depends").is_none(), "missing fields aren't serialized");
+
+        // the `Package` alias takes the serialized names back
+        let back: Control = serde_json::from_value(json).expect("Failed to deserialize control");
+        assert_eq!(back, ctrl);
+
+        // from_map normalizes whatever case and dashes the keys come in
+        let map = HashMap::from([
+            ("package", "hello"),
+            ("VERSION", "1.0-1"),
+            ("Architecture", "amd64"),
+            ("maintainer", "Jane Doe <jane@example.org>"),
+            ("installed-size", "42"),
+            ("Pre-Depends", "libc6 (>= 2.34)"),
+            ("description", "says hello"),
+        ].map(|(k, v)| (k.to_string(), v.to_string())));
+        assert_eq!(from_map(map).expect("Failed to read control from map"), ctrl);
+    }
+}
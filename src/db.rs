@@ -0,0 +1,270 @@
+use std::{ffi::{c_char, c_void, CStr}, path::Path};
+
+use log::debug;
+use sqlite3::{Connection, State};
+
+use crate::control::{self, Control};
+
+type Migration = fn(&Connection) -> Result<(), sqlite3::Error>;
+
+const BUSY_TIMEOUT_MS: usize = 30_000;
+
+// primary result codes, extended ones keep them in the low byte
+const SQLITE_CORRUPT: isize = 11;
+const SQLITE_NOTADB: isize = 26;
+
+// applied in order to every database whose `user_version` is behind, never edit an
+// existing entry, append a new one instead
+const MIGRATIONS: &[(i64, Migration)] = &[
+    (1, create_tables),
+    (2, index_package),
+    (3, create_backups),
+    (4, key_backups_by_deb),
+    (5, split_descriptions),
+];
+
+/// The `user_version` of a database every migration has been applied to
+pub const LATEST_VERSION: i64 = MIGRATIONS[MIGRATIONS.len() - 1].0;
+
+/// Opens a database, with `debug_sql` every statement run on it is logged (bound parameters filled in)
+pub fn open<P: AsRef<Path>>(path: P, debug_sql: bool) -> Result<Connection, sqlite3::Error> {
+    let mut conn = Connection::open(path)?;
+    // another debby holding the write lock is waited for instead of failing right away
+    conn.set_busy_timeout(BUSY_TIMEOUT_MS)?;
+
+    if debug_sql {
+        // the legacy trace hook gets the statement text with its parameters already expanded
+        unsafe { sqlite3_sys::sqlite3_trace(conn.as_raw(), Some(log_statement), std::ptr::null_mut()) };
+    }
+
+    Ok(conn)
+}
+
+extern "C" fn log_statement(_: *mut c_void, sql: *const c_char) {
+    // sqlite hands us a valid nul terminated string that lives for the duration of the call
+    let sql = unsafe { CStr::from_ptr(sql) };
+    debug!(target: "debby::sql", "{}", sql.to_string_lossy());
+}
+
+/// Copies a whole database into a new in-memory one, e.g. to migrate it without writing to the file
+pub fn copy_to_memory(conn: &Connection, debug_sql: bool) -> Result<Connection, sqlite3::Error> {
+    let copy = open(":memory:", debug_sql)?;
+
+    // both connections outlive the backup, which is finished before they're used again
+    let code = unsafe {
+        let backup = sqlite3_sys::sqlite3_backup_init(copy.as_raw(), c"main".as_ptr(), conn.as_raw(), c"main".as_ptr());
+        if backup.is_null() {
+            sqlite3_sys::sqlite3_errcode(copy.as_raw())
+        } else {
+            sqlite3_sys::sqlite3_backup_step(backup, -1);
+            sqlite3_sys::sqlite3_backup_finish(backup)
+        }
+    };
+
+    if code == sqlite3_sys::SQLITE_OK {
+        Ok(copy)
+    } else {
+        let message = unsafe { CStr::from_ptr(sqlite3_sys::sqlite3_errstr(code)) };
+        Err(sqlite3::Error { code: Some(code as isize), message: Some(message.to_string_lossy().into_owned()) })
+    }
+}
+
+pub fn user_version(conn: &Connection) -> Result<i64, sqlite3::Error> {
+    let mut stmt = conn.prepare("PRAGMA user_version")?;
+    stmt.next()?;
+    stmt.read::<i64>(0)
+}
+
+/// Why `check_integrity` failed
+#[derive(Debug)]
+pub enum IntegrityError {
+    /// The database is damaged or isn't a database at all, what's wrong with it
+    Corrupt(String),
+    /// The check itself couldn't run, e.g. because the database is locked
+    Db(sqlite3::Error),
+}
+
+impl std::fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IntegrityError::Corrupt(problems) => write!(f, "{}", problems),
+            IntegrityError::Db(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<sqlite3::Error> for IntegrityError {
+    fn from(e: sqlite3::Error) -> Self {
+        match e.code.map(|code| code & 0xff) {
+            Some(SQLITE_CORRUPT | SQLITE_NOTADB) => IntegrityError::Corrupt(e.to_string()),
+            _ => IntegrityError::Db(e),
+        }
+    }
+}
+
+/// Runs sqlite's integrity check. Only a damaged file (or one that isn't a database at all) is `Corrupt`,
+/// anything else like a busy database is passed on as is
+pub fn check_integrity(conn: &Connection) -> Result<(), IntegrityError> {
+    let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+    let mut problems = vec![];
+
+    while stmt.next()? == State::Row {
+        let row = stmt.read::<String>(0)?;
+        if row != "ok" {
+            problems.push(row);
+        }
+    }
+
+    if problems.is_empty() { Ok(()) } else { Err(IntegrityError::Corrupt(problems.join("; "))) }
+}
+
+pub fn migrate(conn: &Connection) -> Result<(), sqlite3::Error> {
+    let current = user_version(conn)?;
+
+    for (version, migration) in MIGRATIONS.iter().filter(|(version, _)| *version > current) {
+        debug!("Migrating database to version {}", version);
+
+        conn.execute("BEGIN")?;
+        if let Err(e) = migration(conn).and_then(|_| conn.execute(format!("PRAGMA user_version = {}", version))) {
+            let _ = conn.execute("ROLLBACK");
+            return Err(e);
+        }
+        conn.execute("COMMIT")?;
+    }
+
+    Ok(())
+}
+
+// IF NOT EXISTS because databases from before versioning already have these tables
+fn create_tables(conn: &Connection) -> Result<(), sqlite3::Error> {
+    conn.execute(
+        format!(
+            "CREATE TABLE IF NOT EXISTS debs (
+                id INTEGER PRIMARY KEY,
+                {},
+                installed TEXT
+            )",
+            Control::sql_fields()
+        )
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS history (
+            id INTEGER PRIMARY KEY,
+            operation TEXT NOT NULL,
+            package TEXT NOT NULL,
+            version TEXT NOT NULL,
+            timestamp TEXT NOT NULL DEFAULT (datetime('now')),
+            file_count INTEGER NOT NULL
+        )"
+    )
+}
+
+// package lookups go through lower(package) (see control::normalize_package_name), so index that
+fn index_package(conn: &Connection) -> Result<(), sqlite3::Error> {
+    conn.execute("CREATE INDEX IF NOT EXISTS idx_debs_package ON debs(lower(package))")
+}
+
+// files that weren't debby's and got moved out of the way by `install --force-overwrite`
+fn create_backups(conn: &Connection) -> Result<(), sqlite3::Error> {
+    conn.execute(
+        "CREATE TABLE backups (
+            id INTEGER PRIMARY KEY,
+            package TEXT NOT NULL,
+            path TEXT NOT NULL,
+            backup TEXT NOT NULL
+        )"
+    )
+}
+
+// a package can be installed more than once, so backups belong to the row that made them
+fn key_backups_by_deb(conn: &Connection) -> Result<(), sqlite3::Error> {
+    conn.execute(
+        "CREATE TABLE deb_backups (
+            id INTEGER PRIMARY KEY,
+            deb_id INTEGER NOT NULL,
+            path TEXT NOT NULL,
+            backup TEXT NOT NULL
+        )"
+    )?;
+
+    conn.execute(
+        "INSERT INTO deb_backups (deb_id, path, backup)
+            SELECT (SELECT max(debs.id) FROM debs WHERE debs.package = backups.package), path, backup
+            FROM backups
+            WHERE EXISTS (SELECT 1 FROM debs WHERE debs.package = backups.package)"
+    )?;
+
+    conn.execute("DROP TABLE backups")?;
+    conn.execute("ALTER TABLE deb_backups RENAME TO backups")
+}
+
+// the synopsis alone makes a clean column for `all`, rows from before get theirs split from the description
+fn split_descriptions(conn: &Connection) -> Result<(), sqlite3::Error> {
+    conn.execute("ALTER TABLE debs ADD COLUMN description_short TEXT")?;
+    conn.execute("ALTER TABLE debs ADD COLUMN description_long TEXT")?;
+
+    let mut rows = vec![];
+    let mut stmt = conn.prepare("SELECT id, description FROM debs")?;
+    while stmt.next()? == State::Row {
+        rows.push((stmt.read::<i64>(0)?, stmt.read::<String>(1).unwrap_or_default()));
+    }
+
+    for (id, description) in rows {
+        let (short, long) = control::split_description(&description);
+
+        let mut update = conn.prepare("UPDATE debs SET description_short = ?, description_long = ? WHERE id = ?")?;
+        update.bind(1, short.as_str())?;
+        match long {
+            Some(long) => update.bind(2, long.as_str())?,
+            None => update.bind(2, ())?,
+        }
+        update.bind(3, id)?;
+        update.next()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the details of every step sqlite would take to run `query`
+    fn query_plan(conn: &Connection, query: &str) -> Vec<String> {
+        let mut stmt = conn.prepare(format!("EXPLAIN QUERY PLAN {}", query)).expect("Failed to prepare statement");
+        let mut plan = vec![];
+        while stmt.next().expect("Failed to get row") == State::Row {
+            plan.push(stmt.read::<String>(3).expect("Failed to read plan detail"));
+        }
+        plan
+    }
+
+    #[test]
+    fn package_lookups_use_the_index() {
+        let conn = sqlite3::open(":memory:").expect("Failed to open database");
+        migrate(&conn).expect("Failed to migrate database");
+
+        let plan = query_plan(&conn, "SELECT * FROM debs WHERE lower(package) = 'curl'");
+        assert!(plan.iter().any(|step| step.contains("USING INDEX idx_debs_package")), "{:?}", plan);
+    }
+
+    #[test]
+    fn old_databases_are_migrated_in_memory() {
+        // what debby created before the database was versioned
+        let conn = open(":memory:", false).expect("Failed to open database");
+        conn.execute(format!("CREATE TABLE debs (id INTEGER PRIMARY KEY, {}, installed TEXT)", Control::sql_fields()))
+            .and_then(|_| conn.execute("INSERT INTO debs (package, version, description) VALUES ('hello', '1.0', 'says hello')"))
+            .expect("Failed to set up old database");
+
+        let copy = copy_to_memory(&conn, false).expect("Failed to copy database");
+        migrate(&copy).expect("Failed to migrate copy");
+
+        assert_eq!(user_version(&copy).expect("Failed to read version"), LATEST_VERSION);
+        assert_eq!(user_version(&conn).expect("Failed to read version"), 0);
+        let mut stmt = copy.prepare("SELECT description_short FROM debs").expect("Failed to prepare statement");
+        assert_eq!(stmt.next().expect("Failed to get row"), State::Row);
+        assert_eq!(stmt.read::<String>(0).expect("Failed to read description"), "says hello");
+        assert!(conn.execute("SELECT * FROM history").is_err());
+    }
+}
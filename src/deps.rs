@@ -0,0 +1,353 @@
+use std::{cmp::Ordering, str::FromStr};
+
+use sqlite3::{Connection, State};
+
+use crate::control::{self, Control};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VersionOp {
+    Lt,
+    Le,
+    Eq,
+    Ge,
+    Gt,
+}
+
+impl VersionOp {
+    fn matches(&self, ord: Ordering) -> bool {
+        match self {
+            VersionOp::Lt => ord == Ordering::Less,
+            VersionOp::Le => ord != Ordering::Greater,
+            VersionOp::Eq => ord == Ordering::Equal,
+            VersionOp::Ge => ord != Ordering::Less,
+            VersionOp::Gt => ord == Ordering::Greater,
+        }
+    }
+}
+
+/// A single entry of a relationship field, e.g. `libc6 (>= 2.34)`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Relation {
+    pub package: String,
+    pub constraint: Option<(VersionOp, String)>,
+}
+
+impl Relation {
+    pub fn parse(s: &str) -> Option<Relation> {
+        // drop architecture restrictions ([amd64]) and build profiles (<!nocheck>)
+        let s = match s.find(')') {
+            Some(end) => &s[..end],
+            None => s.split(['[', '<']).next().unwrap_or(""),
+        }.trim();
+
+        let (name, constraint) = match s.split_once('(') {
+            Some((name, rest)) => {
+                let rest = rest.trim_end_matches(')').trim();
+                let op_len = rest.find(|c: char| !"<>=".contains(c)).unwrap_or(rest.len());
+                let op = match &rest[..op_len] {
+                    "<<" => VersionOp::Lt,
+                    "<=" | "<" => VersionOp::Le,
+                    "=" => VersionOp::Eq,
+                    ">=" | ">" => VersionOp::Ge,
+                    ">>" => VersionOp::Gt,
+                    _ => return None,
+                };
+                (name, Some((op, rest[op_len..].trim().to_string())))
+            },
+            None => (s, None),
+        };
+
+        // strip multiarch qualifiers like `python3:any`
+        let name = name.trim().split(':').next().unwrap_or("").to_string();
+        if name.is_empty() {
+            return None;
+        }
+
+        Some(Relation { package: name, constraint })
+    }
+
+    pub fn satisfied_by(&self, version: Option<&str>) -> bool {
+        match (&self.constraint, version) {
+            (None, _) | (Some(_), None) => true,
+            (Some((op, wanted)), Some(version)) => op.matches(compare_versions(version, wanted)),
+        }
+    }
+}
+
+impl std::fmt::Display for Relation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.constraint {
+            Some((op, version)) => {
+                let op = match op {
+                    VersionOp::Lt => "<<",
+                    VersionOp::Le => "<=",
+                    VersionOp::Eq => "=",
+                    VersionOp::Ge => ">=",
+                    VersionOp::Gt => ">>",
+                };
+                write!(f, "{} ({} {})", self.package, op, version)
+            },
+            None => write!(f, "{}", self.package),
+        }
+    }
+}
+
+/// Parses a relationship field into groups of alternatives (`a | b, c` -> `[[a, b], [c]]`)
+pub fn parse_relations(field: &str) -> Vec<Vec<Relation>> {
+    field
+        .split(',')
+        .map(|group| group.split('|').filter_map(Relation::parse).collect::<Vec<_>>())
+        .filter(|group| !group.is_empty())
+        .collect()
+}
+
+/// A package passed via `--assume-installed pkg[=version]`
+#[derive(Clone, Debug)]
+pub struct AssumeInstalled {
+    pub package: String,
+    pub version: Option<String>,
+}
+
+impl FromStr for AssumeInstalled {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (package, version) = match s.split_once('=') {
+            Some((package, version)) => (package.trim(), Some(version.trim().to_string())),
+            None => (s.trim(), None),
+        };
+
+        if package.is_empty() {
+            return Err("package name can't be empty".to_string());
+        }
+
+        Ok(AssumeInstalled { package: package.to_string(), version })
+    }
+}
+
+// something that can satisfy a relation: a real package or a virtual one from `Provides`
+struct Provider {
+    name: String,
+    version: Option<String>,
+    // unversioned virtual packages only satisfy unversioned relations
+    is_virtual: bool,
+}
+
+impl Provider {
+    fn satisfies(&self, rel: &Relation) -> bool {
+        self.name == rel.package && match &self.version {
+            Some(version) => rel.satisfied_by(Some(version)),
+            None => !self.is_virtual || rel.constraint.is_none(),
+        }
+    }
+}
+
+struct InstalledPackage {
+    package: String,
+    version: String,
+    provides: Option<String>,
+    conflicts: Option<String>,
+    depends: Option<String>,
+    pre_depends: Option<String>,
+}
+
+fn installed_packages(conn: &Connection) -> Result<Vec<InstalledPackage>, sqlite3::Error> {
+    let mut packages = vec![];
+
+    let mut stmt = conn.prepare("SELECT package, version, provides, conflicts, depends, pre_depends FROM debs")?;
+    while stmt.next()? == State::Row {
+        packages.push(InstalledPackage {
+            package: stmt.read::<String>(0)?,
+            version: stmt.read::<String>(1)?,
+            provides: stmt.read::<String>(2).ok(),
+            conflicts: stmt.read::<String>(3).ok(),
+            depends: stmt.read::<String>(4).ok(),
+            pre_depends: stmt.read::<String>(5).ok(),
+        });
+    }
+
+    Ok(packages)
+}
+
+fn providers_of(package: &str, version: &str, provides: Option<&str>) -> Vec<Provider> {
+    let mut providers = vec![Provider {
+        name: package.to_string(),
+        version: Some(version.to_string()),
+        is_virtual: false,
+    }];
+
+    for (name, version) in provides.map(control::parse_provides).unwrap_or_default() {
+        providers.push(Provider { name, version, is_virtual: true });
+    }
+
+    providers
+}
+
+/// Returns every `Depends`/`Pre-Depends` group of `ctrl` that isn't satisfied by an installed or assumed package
+pub fn unmet_depends(conn: &Connection, ctrl: &Control, assumed: &[AssumeInstalled]) -> Result<Vec<Vec<Relation>>, sqlite3::Error> {
+    let mut available: Vec<Provider> = assumed.iter()
+        .map(|a| Provider {
+            name: a.package.clone(),
+            version: a.version.clone(),
+            is_virtual: false,
+        })
+        .collect();
+
+    for pkg in installed_packages(conn)? {
+        available.extend(providers_of(&pkg.package, &pkg.version, pkg.provides.as_deref()));
+    }
+
+    // a package may satisfy its own dependencies through what it provides
+    available.extend(
+        providers_of(&ctrl.package, &ctrl.version, ctrl.provides.as_deref())
+            .into_iter()
+            .filter(|p| p.is_virtual)
+    );
+
+    let fields = [&ctrl.pre_depends, &ctrl.depends];
+    let unmet = fields.into_iter()
+        .flatten()
+        .flat_map(|field| parse_relations(field))
+        .filter(|group| !group.iter().any(|rel| available.iter().any(|p| p.satisfies(rel))))
+        .collect();
+
+    Ok(unmet)
+}
+
+/// Returns `(installed package, relation)` for every conflict between `ctrl` and the installed packages, in either direction
+pub fn conflicts(conn: &Connection, ctrl: &Control) -> Result<Vec<(String, Relation)>, sqlite3::Error> {
+    let own_conflicts = ctrl.conflicts.as_deref().map(parse_relations).unwrap_or_default();
+    let own_providers = providers_of(&ctrl.package, &ctrl.version, ctrl.provides.as_deref());
+
+    let mut found = vec![];
+
+    // reinstalling the same package isn't a conflict
+    for pkg in installed_packages(conn)?.into_iter().filter(|pkg| pkg.package != ctrl.package) {
+        let providers = providers_of(&pkg.package, &pkg.version, pkg.provides.as_deref());
+
+        for rel in own_conflicts.iter().flatten() {
+            if providers.iter().any(|p| p.satisfies(rel)) {
+                found.push((pkg.package.clone(), rel.clone()));
+            }
+        }
+
+        let their_conflicts = pkg.conflicts.as_deref().map(parse_relations).unwrap_or_default();
+        for rel in their_conflicts.iter().flatten() {
+            if own_providers.iter().any(|p| p.satisfies(rel)) {
+                found.push((pkg.package.clone(), rel.clone()));
+            }
+        }
+    }
+
+    Ok(found)
+}
+
+/// Returns the installed packages that would be left with an unmet `Depends`/`Pre-Depends` if `ctrl` was uninstalled
+pub fn reverse_depends(conn: &Connection, ctrl: &Control) -> Result<Vec<String>, sqlite3::Error> {
+    let removed = providers_of(&ctrl.package, &ctrl.version, ctrl.provides.as_deref());
+    let others: Vec<InstalledPackage> = installed_packages(conn)?.into_iter()
+        .filter(|pkg| pkg.package != ctrl.package)
+        .collect();
+    let remaining: Vec<Provider> = others.iter()
+        .flat_map(|pkg| providers_of(&pkg.package, &pkg.version, pkg.provides.as_deref()))
+        .collect();
+
+    let mut needed_by = vec![];
+
+    for pkg in &others {
+        let fields = [&pkg.pre_depends, &pkg.depends];
+        let broken = fields.into_iter()
+            .flatten()
+            .flat_map(|field| parse_relations(field))
+            .any(|group| {
+                group.iter().any(|rel| removed.iter().any(|p| p.satisfies(rel)))
+                    && !group.iter().any(|rel| remaining.iter().any(|p| p.satisfies(rel)))
+            });
+
+        if broken && !needed_by.contains(&pkg.package) {
+            needed_by.push(pkg.package.clone());
+        }
+    }
+
+    Ok(needed_by)
+}
+
+/// Compares two Debian version strings (`[epoch:]upstream[-revision]`) the way dpkg does
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (a_epoch, a_upstream, a_revision) = split_version(a);
+    let (b_epoch, b_upstream, b_revision) = split_version(b);
+
+    a_epoch.cmp(&b_epoch)
+        .then_with(|| verrevcmp(a_upstream, b_upstream))
+        .then_with(|| verrevcmp(a_revision, b_revision))
+}
+
+fn split_version(v: &str) -> (u64, &str, &str) {
+    let v = v.trim();
+    let (epoch, rest) = match v.split_once(':') {
+        Some((epoch, rest)) => (epoch.parse().unwrap_or(0), rest),
+        None => (0, v),
+    };
+
+    match rest.rsplit_once('-') {
+        Some((upstream, revision)) => (epoch, upstream, revision),
+        None => (epoch, rest, ""),
+    }
+}
+
+// '~' sorts before everything (even the end of the string), letters before other characters
+fn order(c: Option<u8>) -> i32 {
+    match c {
+        None => 0,
+        Some(b'~') => -1,
+        Some(c) if c.is_ascii_digit() => 0,
+        Some(c) if c.is_ascii_alphabetic() => c as i32,
+        Some(c) => c as i32 + 256,
+    }
+}
+
+fn verrevcmp(a: &str, b: &str) -> Ordering {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let (mut i, mut j) = (0, 0);
+
+    let is_digit = |s: &[u8], idx: usize| s.get(idx).is_some_and(|c| c.is_ascii_digit());
+
+    while i < a.len() || j < b.len() {
+        while (i < a.len() && !is_digit(a, i)) || (j < b.len() && !is_digit(b, j)) {
+            let (ac, bc) = (order(a.get(i).copied()), order(b.get(j).copied()));
+            if ac != bc {
+                return ac.cmp(&bc);
+            }
+            i += 1;
+            j += 1;
+        }
+
+        while a.get(i) == Some(&b'0') {
+            i += 1;
+        }
+        while b.get(j) == Some(&b'0') {
+            j += 1;
+        }
+
+        let mut first_diff = Ordering::Equal;
+        while is_digit(a, i) && is_digit(b, j) {
+            if first_diff == Ordering::Equal {
+                first_diff = a[i].cmp(&b[j]);
+            }
+            i += 1;
+            j += 1;
+        }
+
+        if is_digit(a, i) {
+            return Ordering::Greater;
+        }
+        if is_digit(b, j) {
+            return Ordering::Less;
+        }
+        if first_diff != Ordering::Equal {
+            return first_diff;
+        }
+    }
+
+    Ordering::Equal
+}
@@ -0,0 +1,116 @@
+use std::{io::{Read, Write}, path::{Path, PathBuf}, process::{Command, Stdio}};
+
+use clio::Output;
+use flate2::read::GzDecoder;
+use log::{error, warn};
+use sqlite3::Connection;
+
+use crate::{control::{self, ControlWithData}, extract::{self, Limits}};
+
+/// Where packages keep the file in `/usr/share/doc/<package>/`, in order of preference.
+/// Native packages have a `changelog` rather than a `changelog.Debian`
+pub const CHANGELOG: &[&str] = &["changelog.Debian.gz", "changelog.gz", "changelog.Debian", "changelog"];
+pub const COPYRIGHT: &[&str] = &["copyright"];
+
+/// Prints one of the `names` files from the doc directory of an installed package, or of a package if `pkg` is a `.deb`
+pub fn show(conn: Connection, pkg: &str, what: &str, names: &[&str], limits: Limits, out: &mut Output) {
+    let found = if extract::is_deb(Path::new(pkg)) {
+        from_deb(Path::new(pkg), names, limits)
+    } else {
+        from_installed(&conn, pkg, names)
+    };
+
+    let Some((path, contents)) = found else {
+        error!("{} has no {} (looked for {} in /usr/share/doc)", pkg, what, names.join(", "));
+        std::process::exit(1);
+    };
+
+    let contents = if path.extension().is_some_and(|ext| ext == "gz") {
+        let mut decompressed = vec![];
+        if let Err(e) = GzDecoder::new(contents.as_slice()).read_to_end(&mut decompressed) {
+            error!("Failed to decompress {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+        decompressed
+    } else {
+        contents
+    };
+
+    page(out, &String::from_utf8_lossy(&contents));
+}
+
+// the first of `names` found in the doc directory of the package in a .deb
+fn from_deb(deb: &Path, names: &[&str], limits: Limits) -> Option<(PathBuf, Vec<u8>)> {
+    if !deb.exists() {
+        error!("Failed to read .deb file because the .deb file you specified does not exist.");
+        std::process::exit(-1);
+    }
+
+    extract::check_members(deb);
+
+    let Some(ctrl) = extract::extract_control(extract::open(deb)).and_then(|ctrl| control::parse_control(ctrl).ok()) else {
+        error!("Failed to get control file from .deb, make sure the .deb is valid");
+        std::process::exit(-1);
+    };
+
+    let paths = extract::data_paths(extract::open(deb), limits)
+        .unwrap_or_else(|e| extract::extract_failed(deb, e));
+
+    let doc_dir = Path::new("usr/share/doc").join(&ctrl.package);
+    let path = names.iter()
+        .map(|name| doc_dir.join(name))
+        .find(|path| paths.iter().any(|(p, is_dir)| !is_dir && p == path))?;
+
+    // only regular files are read, a symlink (e.g. to another package's doc directory) can't be followed from inside the archive
+    let contents = extract::read_data_file(extract::open(deb), &path, limits)
+        .unwrap_or_else(|e| extract::extract_failed(deb, e));
+    if contents.is_none() {
+        warn!("/{} isn't a regular file, skipping...", path.display());
+    }
+
+    contents.map(|contents| (path, contents))
+}
+
+// the first of `names` in the doc directory of an installed package that's still on disk
+fn from_installed(conn: &Connection, package: &str, names: &[&str]) -> Option<(PathBuf, Vec<u8>)> {
+    let installed = match ControlWithData::latest(conn, package) {
+        Ok(installed) => installed,
+        Err(_) => {
+            error!("{} is not installed", package);
+            std::process::exit(1);
+        }
+    };
+
+    // recorded paths already have --prefix-map applied
+    let doc_dir = format!("/usr/share/doc/{}/", installed.ctrl.package);
+    let files: Vec<&str> = installed.installed.split(',').map(str::trim).filter(|s| !s.is_empty()).collect();
+
+    names.iter()
+        .filter_map(|name| files.iter().find(|path| path.ends_with(&format!("{}{}", doc_dir, name))))
+        .find_map(|path| match std::fs::read(path) {
+            Ok(contents) => Some((PathBuf::from(path), contents)),
+            Err(e) => {
+                warn!("Failed to read {}: {}", path, e);
+                None
+            },
+        })
+}
+
+/// Shows `text` through `$PAGER` (or `less`) when writing to a terminal, writes it as is otherwise
+pub fn page(out: &mut Output, text: &str) {
+    if out.is_tty() {
+        let pager = std::env::var("PAGER").ok().filter(|pager| !pager.trim().is_empty()).unwrap_or_else(|| "less".to_string());
+
+        if let Ok(mut child) = Command::new("sh").arg("-c").arg(&pager).stdin(Stdio::piped()).spawn() {
+            // the pager quitting early isn't an error
+            if let Some(mut stdin) = child.stdin.take() {
+                let _ = stdin.write_all(text.as_bytes());
+            }
+            let _ = child.wait();
+            return;
+        }
+        warn!("Failed to run pager {}, printing instead", pager);
+    }
+
+    write!(out, "{}", text).expect("Failed to write output");
+}
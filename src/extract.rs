@@ -1,7 +1,8 @@
-use std::{collections::HashMap, fs::{self, File}, io::{Read, Seek}, path::{Path, PathBuf}};
+use std::{cmp::Reverse, collections::{BinaryHeap, HashMap}, fmt, fs::{self, File}, io::{BufRead, BufReader, IsTerminal, Read, Seek, Write}, path::{Component, Path, PathBuf}, sync::{Arc, atomic::{AtomicU64, Ordering}}, time::{Duration, Instant}};
 
 use ar::Archive;
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressState, ProgressStyle, style::ProgressTracker};
+use log::{debug, error};
 use ptree::TreeBuilder;
 use tar::{Archive as TarArchive, EntryType};
 
@@ -10,45 +11,144 @@ use flate2::read::GzDecoder;
 use xz2::read::XzDecoder;
 use zstd::stream::read::Decoder as ZstdDecoder;
 
-pub fn extract_to(extract_dir: PathBuf, f: File) {
-    let _ = fs::create_dir_all(&extract_dir); // error silently
+use crate::interrupt;
 
-    let mut f = f.try_clone().expect("Failed to clone file");
+/// Whether `path` has a `.deb` extension, in any case (`PACKAGE.DEB` too)
+pub fn is_deb(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("deb"))
+}
 
-    let files = count(&f);
-    let bar = ProgressBar::new(files as u64);
+/// Opens a package for another pass over it, every pass gets a fresh handle instead of seeking a shared one
+pub fn open(deb: &Path) -> File {
+    File::open(deb).unwrap_or_else(|e| extract_failed(deb, e))
+}
 
-    bar.set_style(ProgressStyle::default_bar()
-        .template("{spinner:.green} [{elapsed_precise}] [{percent_precise}] [{wide_bar:.cyan/blue}] {pos}/{human_len} ({eta}) {msg}")
-        .unwrap()
-        .progress_chars("#>-"));
-
-    let _ = f.seek(std::io::SeekFrom::Start(0));
-
-    let mut archive = Archive::new(f.try_clone().expect("Failed to clone file"));
-
-    while let Some(entry) = archive.next_entry().transpose().expect("Failed to transpose new entry") {
-        let name = String::from_utf8_lossy(entry.header().identifier())
-            .trim()
-            .trim_end_matches('/')
-            .to_string();
-
-        let decoder: Option<Box<dyn Read>> = if name.ends_with(".tar.gz") {
-            Some(Box::new(GzDecoder::new(entry)))
-        } else if name.ends_with(".tar.xz") {
-            Some(Box::new(XzDecoder::new(entry)))
-        } else if name.ends_with(".tar.bz2") {
-            Some(Box::new(BzDecoder::new(entry)))
-        } else if name.ends_with(".tar.zst") {
-            ZstdDecoder::new(entry)
-                .ok()
-                .map(|decoder| Box::new(decoder) as Box<dyn Read>)
-        } else {
-            None
+// the name of an ar member, e.g. `control.tar.gz`. The ar crate already drops the space padding, the `/` GNU ar
+// ends names with and the NUL padding of BSD `#1/<len>` names, but it only drops the `/` once it decided the
+// archive is GNU style, so it's trimmed here too for archives that mix both
+fn member_name(header: &ar::Header) -> String {
+    String::from_utf8_lossy(header.identifier())
+        .trim()
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Makes sure a `.deb` is an installable binary package before anything else reads it: an ar archive with a
+/// `debian-binary`, a `control.tar.*` and a `data.tar.*` member. Source artifacts or tarballs renamed to `.deb`
+/// get a specific error instead of failing somewhere along the way
+pub fn check_members(deb: &Path) {
+    let mut archive = Archive::new(open(deb));
+    let (mut debian_binary, mut control, mut data) = (false, false, false);
+
+    while let Some(entry) = archive.next_entry() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                error!("{} isn't a Debian binary package, it's not a valid ar archive: {}", deb.display(), e);
+                std::process::exit(1);
+            }
         };
+        let name = member_name(entry.header());
+
+        debian_binary |= name == "debian-binary";
+        control |= name.starts_with("control.tar");
+        data |= name.starts_with("data.tar");
+    }
+
+    let problem = match (debian_binary, control, data) {
+        (true, true, true) => return,
+        (false, false, false) => "it has none of the debian-binary, control.tar and data.tar members",
+        (_, true, false) => "this looks like a control-only/source artifact, not an installable binary package (there's no data.tar member)",
+        (_, false, true) => "there's a data.tar member but no control.tar member to tell what package it is",
+        (true, false, false) => "it has neither a control.tar nor a data.tar member",
+        (false, _, _) => "there's no debian-binary member",
+    };
+
+    error!("{} isn't a Debian binary package: {}", deb.display(), problem);
+    std::process::exit(1);
+}
+
+/// Bails out of a command when a package can't be read
+pub fn extract_failed(deb: &Path, e: std::io::Error) -> ! {
+    error!("Failed to extract {}: {}", deb.display(), e);
+    std::process::exit(1);
+}
+
+/// Parses a size like `10G`, `512MiB` or `4096` (bytes), multiples are powers of 1024
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let number: u64 = number.parse().map_err(|_| format!("expected a size like 10G, got {:?}", s))?;
+    let shift = match unit.trim().to_ascii_lowercase().trim_end_matches('b').trim_end_matches('i') {
+        "" => 0,
+        "k" => 10,
+        "m" => 20,
+        "g" => 30,
+        "t" => 40,
+        _ => return Err(format!("unknown size unit {:?}, expected K, M, G or T", unit)),
+    };
+
+    number.checked_mul(1 << shift).ok_or_else(|| format!("{} is too big", s))
+}
+
+/// How much a package is allowed to unpack to before debby gives up on it
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Uncompressed bytes per archive member
+    pub max_uncompressed_size: u64,
+    /// Entries per archive member
+    pub max_entries: u64,
+}
+
+// errors out once a decompressor has produced more than `limit` bytes
+struct SizeLimit<R> {
+    inner: R,
+    name: String,
+    limit: u64,
+    read: u64,
+}
+
+impl<R: Read> Read for SizeLimit<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+
+        if self.read > self.limit {
+            return Err(std::io::Error::other(format!(
+                "{} unpacks to more than {} (see --max-uncompressed-size)", self.name, HumanBytes(self.limit)
+            )));
+        }
+
+        Ok(n)
+    }
+}
+
+pub fn extract_to(extract_dir: PathBuf, mut f: File, quiet: bool, unit: ProgressUnit, template: Option<&str>, limits: Limits) -> std::io::Result<()> {
+    let _ = fs::create_dir_all(&extract_dir); // error silently
+
+    let (total, bytes) = if quiet { (0, 0) } else { count(&f, unit, limits)? };
+    let eta = MixedEta::new(bytes);
+    let bar = progress_bar(total, unit, template, quiet, &eta);
+
+    f.seek(std::io::SeekFrom::Start(0))?;
+
+    let mut archive = Archive::new(f);
+
+    while let Some(entry) = archive.next_entry().transpose()? {
+        let name = member_name(entry.header());
+
+        let decoder = decoder(&name, entry, limits.max_uncompressed_size)?;
 
         if let Some(decoder) = decoder {
+            let decoder = match unit {
+                ProgressUnit::Files => decoder,
+                ProgressUnit::Bytes => Box::new(bar.wrap_read(decoder)),
+            };
             let mut tar = TarArchive::new(decoder);
+            // keep the setuid/setgid bits around, copy() decides whether they get installed
+            tar.set_preserve_permissions(true);
             let dst = extract_dir.join(
                 Path::new(&name)
                     .file_name()
@@ -58,27 +158,39 @@ pub fn extract_to(extract_dir: PathBuf, f: File) {
             );
 
             if dst.symlink_metadata().is_err() {
-                fs::create_dir_all(&dst)
-                    .expect("Failed to create_dir_all");
+                fs::create_dir_all(&dst)?;
             }
 
             let dst = &dst.canonicalize().unwrap_or(dst.to_path_buf());
 
+            // a crafted member name must never make us unpack outside of the extract dir
+            let root = extract_dir.canonicalize().unwrap_or(extract_dir.clone());
+            if !dst.starts_with(&root) {
+                return Err(std::io::Error::other(format!(
+                    "refusing to extract archive member {} to {}, which is outside of {}", name, dst.display(), root.display()
+                )));
+            }
+
             let mut directories = Vec::new();
-            for entry in tar.entries().expect("Failed to get tar entries") {
-                let mut file = entry.expect("Failed to iterate over archive");
+            for entry in limited_entries(&mut tar, &name, limits.max_entries)? {
+                let mut file = entry?;
                 if file.header().entry_type() == EntryType::Directory {
                     directories.push(file);
                 } else {
-                    file.unpack_in(dst).expect("Failed to unpack in dst");
-                    bar.inc(1);
+                    file.unpack_in(dst)?;
+                    if unit == ProgressUnit::Files {
+                        bar.inc(1);
+                        eta.inc(file.size());
+                    }
                 }
             }
 
             directories.sort_by(|a, b| b.path_bytes().cmp(&a.path_bytes()));
             for mut dir in directories {
-                dir.unpack_in(dst).expect("Failed to unpack inner file");
-                bar.inc(1);
+                dir.unpack_in(dst)?;
+                if unit == ProgressUnit::Files {
+                    bar.inc(1);
+                }
             }
 
             // tar.unpack(dst).expect("Failed to unpack tar");
@@ -86,65 +198,372 @@ pub fn extract_to(extract_dir: PathBuf, f: File) {
     }
 
     bar.finish();
+
+    Ok(())
 }
 
-pub fn count(f: &File) -> usize {
-    let mut total = 0;
+// picks the decompressor for a `*.tar.{gz,xz,bz2,zst}` archive member, `None` for anything else.
+// control and data members named otherwise are sniffed, see `sniffed_decoder`. `Err` if the decompressor can't be set up.
+// the decompressed stream fails once it gets bigger than `max_size`, so a tiny archive can't fill the disk
+fn decoder<'a>(name: &str, entry: impl Read + 'a, max_size: u64) -> std::io::Result<Option<Box<dyn Read + 'a>>> {
+    let decoder: Box<dyn Read + 'a> = if name.ends_with(".tar.gz") {
+        Box::new(GzDecoder::new(entry))
+    } else if name.ends_with(".tar.xz") {
+        Box::new(XzDecoder::new(entry))
+    } else if name.ends_with(".tar.bz2") {
+        Box::new(BzDecoder::new(entry))
+    } else if name.ends_with(".tar.zst") {
+        zstd_decoder(entry)?
+    } else if name.ends_with(".tar.lz4") {
+        lz4_decoder(name, entry)?
+    } else if name.starts_with("control.tar") || name.starts_with("data.tar") {
+        match sniffed_decoder(name, entry)? {
+            Some(decoder) => decoder,
+            None => return Ok(None),
+        }
+    } else {
+        return Ok(None);
+    };
+
+    Ok(Some(Box::new(SizeLimit { inner: decoder, name: name.to_string(), limit: max_size, read: 0 })))
+}
+
+// picks the decompressor from the magic bytes a member starts with, for packages whose member names don't say
+// how they're compressed (e.g. a gzipped `control.tar` or a `data.tar.gzip`). A `*.tar` without any of them is read uncompressed
+fn sniffed_decoder<'a>(name: &str, entry: impl Read + 'a) -> std::io::Result<Option<Box<dyn Read + 'a>>> {
+    let mut entry = BufReader::new(entry);
+    // peeking doesn't consume anything, the decompressor still gets to read the magic bytes
+    let magic: Vec<u8> = entry.fill_buf()?.iter().take(6).copied().collect();
+
+    let decoder: Box<dyn Read + 'a> = if magic.starts_with(&[0x1f, 0x8b]) {
+        Box::new(GzDecoder::new(entry))
+    } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Box::new(XzDecoder::new(entry))
+    } else if magic.starts_with(b"BZh") {
+        Box::new(BzDecoder::new(entry))
+    } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        zstd_decoder(entry)?
+    } else if magic.starts_with(&[0x04, 0x22, 0x4d, 0x18]) {
+        lz4_decoder(name, entry)?
+    } else if name.ends_with(".tar") {
+        return Ok(Some(Box::new(entry)));
+    } else {
+        return Ok(None);
+    };
+
+    debug!("{} isn't named after how it's compressed, going by its magic bytes instead", name);
+    Ok(Some(decoder))
+}
+
+// the biggest window zstd supports on this target (ZSTD_WINDOWLOG_MAX), anything above fails to set up
+const ZSTD_WINDOW_LOG_MAX: u32 = if cfg!(target_pointer_width = "64") { 31 } else { 30 };
+
+fn zstd_decoder<'a>(entry: impl Read + 'a) -> std::io::Result<Box<dyn Read + 'a>> {
+    let mut decoder = ZstdDecoder::new(entry)?;
+    // packages compressed with `zstd --long` need a bigger window than the default 128MiB limit
+    decoder.window_log_max(ZSTD_WINDOW_LOG_MAX)?;
+    Ok(Box::new(decoder))
+}
+
+// the entries of a tar archive, failing once there are more than `max_entries` of them
+fn limited_entries<'a, R: Read>(tar: &'a mut TarArchive<R>, name: &str, max_entries: u64) -> std::io::Result<impl Iterator<Item = std::io::Result<tar::Entry<'a, R>>>> {
+    let name = name.to_string();
+
+    Ok(tar.entries()?.enumerate().map(move |(i, entry)| {
+        if i as u64 >= max_entries {
+            return Err(std::io::Error::other(format!("{} has more than {} entries (see --max-entries)", name, max_entries)));
+        }
+        entry
+    }))
+}
+
+/// Turns a path from a data archive (`./usr/bin/foo`) into a path relative to the package root (`usr/bin/foo`),
+/// `None` for the root itself and for paths that try to escape it
+pub fn package_path(path: &Path) -> Option<PathBuf> {
+    let mut rel = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => rel.push(part),
+            Component::CurDir => {},
+            _ => return None,
+        }
+    }
+
+    (!rel.as_os_str().is_empty()).then_some(rel)
+}
+
+/// Calls `on_entry` with every entry of the data archive, without unpacking anything
+pub fn for_each_data_entry(f: File, limits: Limits, mut on_entry: impl FnMut(&mut tar::Entry<'_, Box<dyn Read + '_>>)) -> std::io::Result<()> {
     let mut archive = Archive::new(f);
 
-    while let Some(entry) = archive.next_entry().transpose().expect("Failed to transpose new entry") {
-        let name = String::from_utf8_lossy(entry.header().identifier())
-            .trim()
-            .trim_end_matches('/')
-            .to_string();
-
-        let decoder: Option<Box<dyn Read>> = if name.ends_with(".tar.gz") {
-            Some(Box::new(GzDecoder::new(entry)))
-        } else if name.ends_with(".tar.xz") {
-            Some(Box::new(XzDecoder::new(entry)))
-        } else if name.ends_with(".tar.bz2") {
-            Some(Box::new(BzDecoder::new(entry)))
-        } else if name.ends_with(".tar.zst") {
-            ZstdDecoder::new(entry)
-                .ok()
-                .map(|decoder| Box::new(decoder) as Box<dyn Read>)
-        } else {
-            None
-        };
+    while let Some(entry) = archive.next_entry().transpose()? {
+        let name = member_name(entry.header());
+
+        let decoder = if name.starts_with("data.tar") { decoder(&name, entry, limits.max_uncompressed_size)? } else { None };
 
         if let Some(decoder) = decoder {
             let mut tar = TarArchive::new(decoder);
+            tar.set_preserve_permissions(true);
 
-            total += tar.entries().unwrap().count();
+            for entry in limited_entries(&mut tar, &name, limits.max_entries)? {
+                // stop between entries, so a file is never left half written
+                if interrupt::interrupted() {
+                    return Err(std::io::ErrorKind::Interrupted.into());
+                }
+                on_entry(&mut entry?);
+            }
         }
     }
 
-    total
+    Ok(())
 }
 
-pub fn extract_control(f: File) -> Option<String> {
+/// Lists everything in the data archive relative to the package root, along with whether it's a directory
+pub fn data_paths(f: File, limits: Limits) -> std::io::Result<Vec<(PathBuf, bool)>> {
+    data_paths_with_size(f, limits).map(|(paths, _)| paths)
+}
+
+/// Like `data_paths`, also adding up the size of every file from the tar headers in the same pass
+pub fn data_paths_with_size(f: File, limits: Limits) -> std::io::Result<(Vec<(PathBuf, bool)>, u64)> {
+    let mut paths = Vec::new();
+    let mut size = 0;
+
+    for_each_data_entry(f, limits, |entry| {
+        if let Ok(path) = entry.path()
+            && let Some(rel) = package_path(&path)
+        {
+            paths.push((rel, entry.header().entry_type() == EntryType::Directory));
+            size += entry.size();
+        }
+    })?;
+
+    Ok((paths, size))
+}
+
+/// Reads one regular file out of the data archive without unpacking anything else.
+/// `path` is relative to the package root (`usr/bin/foo`, `./usr/bin/foo` and `/usr/bin/foo` all work), `None` if there's no such file
+pub fn read_data_file(f: File, path: &Path, limits: Limits) -> std::io::Result<Option<Vec<u8>>> {
+    let wanted = package_path(path.strip_prefix("/").unwrap_or(path));
+    let mut contents = None;
+    let mut error = None;
+
+    for_each_data_entry(f, limits, |entry| {
+        if contents.is_some() || error.is_some() || !entry.header().entry_type().is_file() {
+            return;
+        }
+
+        if entry.path().ok().and_then(|path| package_path(&path)) == wanted {
+            let mut buf = vec![];
+            match entry.read_to_end(&mut buf) {
+                Ok(_) => contents = Some(buf),
+                Err(e) => error = Some(e),
+            }
+        }
+    })?;
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(contents),
+    }
+}
+
+/// The `n` biggest regular files in the data archive, biggest first
+pub fn largest_files(f: File, n: usize, limits: Limits) -> std::io::Result<Vec<(PathBuf, u64)>> {
+    // a min-heap of the biggest files so far, so only `n` of them are ever kept around
+    let mut largest = BinaryHeap::new();
+
+    for_each_data_entry(f, limits, |entry| {
+        if entry.header().entry_type().is_file()
+            && let Ok(path) = entry.path()
+            && let Some(rel) = package_path(&path)
+        {
+            largest.push(Reverse((entry.size(), rel)));
+            if largest.len() > n {
+                largest.pop();
+            }
+        }
+    })?;
+
+    Ok(largest.into_sorted_vec().into_iter().map(|Reverse((size, path))| (path, size)).collect())
+}
+
+#[cfg(feature = "lz4")]
+fn lz4_decoder<'a>(_name: &str, entry: impl Read + 'a) -> std::io::Result<Box<dyn Read + 'a>> {
+    Ok(Box::new(lz4_flex::frame::FrameDecoder::new(entry)))
+}
+
+#[cfg(not(feature = "lz4"))]
+fn lz4_decoder<'a>(name: &str, _entry: impl Read + 'a) -> std::io::Result<Box<dyn Read + 'a>> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, format!("{} is compressed with lz4, but lz4 support wasn't compiled in (enable the `lz4` feature)", name)))
+}
+
+// how many files (or uncompressed bytes) there are in all of the package's archives, along with the size of those files
+pub fn count(f: &File, unit: ProgressUnit, limits: Limits) -> std::io::Result<(u64, u64)> {
+    let mut total = 0;
+    let mut bytes = 0;
     let mut archive = Archive::new(f);
 
-    while let Some(entry) = archive.next_entry().transpose().ok()? {
-        let name = String::from_utf8_lossy(entry.header().identifier())
-            .trim()
-            .trim_end_matches('/')
-            .to_string();
-
-        let decoder: Option<Box<dyn Read>> = if name == "control.tar.gz" {
-            Some(Box::new(GzDecoder::new(entry)))
-        } else if name == "control.tar.xz" {
-            Some(Box::new(XzDecoder::new(entry)))
-        } else if name == "control.tar.bz2" {
-            Some(Box::new(BzDecoder::new(entry)))
-        } else if name == "control.tar.zst" {
-            ZstdDecoder::new(entry)
-                .ok()
-                .map(|decoder| Box::new(decoder) as Box<dyn Read>)
+    while let Some(entry) = archive.next_entry().transpose()? {
+        let name = member_name(entry.header());
+
+        let decoder = decoder(&name, entry, limits.max_uncompressed_size)?;
+
+        if let Some(mut decoder) = decoder {
+            match unit {
+                ProgressUnit::Files => {
+                    for entry in limited_entries(&mut TarArchive::new(decoder), &name, limits.max_entries)? {
+                        total += 1;
+                        bytes += entry?.size();
+                    }
+                },
+                ProgressUnit::Bytes => {
+                    let size = std::io::copy(&mut decoder, &mut std::io::sink())?;
+                    total += size;
+                    bytes += size;
+                },
+            }
+        }
+    }
+
+    Ok((total, bytes))
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum ProgressMode {
+    /// Only when stderr is a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+impl ProgressMode {
+    pub fn enabled(self) -> bool {
+        match self {
+            ProgressMode::Auto => std::io::stderr().is_terminal(),
+            ProgressMode::Always => true,
+            ProgressMode::Never => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum ProgressUnit {
+    Files,
+    /// Uncompressed bytes, more accurate for packages with a few huge files
+    Bytes,
+}
+
+/// Makes sure a `--progress-template` is something indicatif can render
+pub fn check_template(template: &str) -> Result<(), String> {
+    ProgressStyle::default_bar().template(template).map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// The `{eta}` of a progress bar that counts files, going by both the files and the bytes of them written so far.
+/// Files alone make for a useless eta when a package has a few huge files, bytes alone when it has lots of tiny
+/// ones (each file costs time of its own), so the progress is the average of the two
+#[derive(Clone)]
+pub struct MixedEta {
+    done: Arc<AtomicU64>,
+    total: u64,
+}
+
+impl MixedEta {
+    pub fn new(total: u64) -> Self {
+        MixedEta { done: Arc::new(AtomicU64::new(0)), total }
+    }
+
+    pub fn inc(&self, bytes: u64) {
+        self.done.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+impl ProgressTracker for MixedEta {
+    fn clone_box(&self) -> Box<dyn ProgressTracker> {
+        Box::new(self.clone())
+    }
+
+    fn tick(&mut self, _: &ProgressState, _: Instant) {}
+
+    fn reset(&mut self, _: &ProgressState, _: Instant) {}
+
+    fn write(&self, state: &ProgressState, w: &mut dyn fmt::Write) {
+        let files = state.len().filter(|&len| len > 0).map(|len| state.pos().min(len) as f64 / len as f64);
+        let bytes = (self.total > 0).then(|| self.done.load(Ordering::Relaxed).min(self.total) as f64 / self.total as f64);
+
+        let progress = match (files, bytes) {
+            (Some(files), Some(bytes)) => (files + bytes) / 2.0,
+            (Some(progress), None) | (None, Some(progress)) => progress,
+            (None, None) => 0.0,
+        };
+
+        // nothing to go by yet, fall back to indicatif's own eta
+        let eta = if progress > 0.0 {
+            state.elapsed().mul_f64((1.0 - progress) / progress)
         } else {
-            None
+            state.eta()
         };
 
+        let _ = write!(w, "{:#}", HumanDuration(eta));
+    }
+}
+
+/// A progress bar counting up to `total` files or bytes, hidden when quiet.
+/// `template` replaces the default indicatif template and must've been checked with `check_template`.
+/// When counting files, `eta` replaces the `{eta}` placeholder so it takes the size of the files into account
+pub fn progress_bar(total: u64, unit: ProgressUnit, template: Option<&str>, quiet: bool, eta: &MixedEta) -> ProgressBar {
+    if quiet {
+        return ProgressBar::hidden();
+    }
+
+    let position = match unit {
+        ProgressUnit::Files => "{pos}/{human_len}",
+        ProgressUnit::Bytes => "{bytes}/{total_bytes}",
+    };
+    let template = template.map(str::to_string).unwrap_or_else(|| {
+        format!("{{spinner:.green}} [{{elapsed_precise}}] [{{percent_precise}}] [{{wide_bar:.cyan/blue}}] {position} ({{eta}}) {{msg}}")
+    });
+
+    let mut style = ProgressStyle::default_bar()
+        .template(&template)
+        .unwrap()
+        .progress_chars("#>-");
+    if unit == ProgressUnit::Files {
+        style = style.with_key("eta", eta.clone());
+    }
+
+    let bar = ProgressBar::new(total);
+    bar.set_style(style);
+
+    bar
+}
+
+pub fn spinner(msg: &'static str, quiet: bool) -> ProgressBar {
+    if quiet {
+        return ProgressBar::hidden();
+    }
+
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(ProgressStyle::default_spinner()
+        .template("{spinner:.green} {msg}")
+        .unwrap());
+    spinner.set_message(msg);
+    spinner.enable_steady_tick(Duration::from_millis(100));
+
+    spinner
+}
+
+// control archives only hold the control file and maintainer scripts
+const MAX_CONTROL_SIZE: u64 = 64 << 20;
+
+pub fn extract_control(f: File) -> Option<String> {
+    let mut archive = Archive::new(f);
+
+    while let Some(entry) = archive.next_entry().transpose().ok()? {
+        let name = member_name(entry.header());
+
+        let decoder = if name.starts_with("control.tar") { decoder(&name, entry, MAX_CONTROL_SIZE).ok()? } else { None };
+
         if let Some(decoder) = decoder {
             let mut tar = TarArchive::new(decoder);
 
@@ -152,7 +571,9 @@ pub fn extract_control(f: File) -> Option<String> {
                 let mut file = entry.ok()?;
                 let path = file.path().ok()?;
 
-                if let Some(fname) = path.file_name() && fname == "control" {
+                // `./control` and `control` are both the control file, `foo/control` isn't
+                let mut components = path.components().filter(|c| *c != std::path::Component::CurDir);
+                if components.next().is_some_and(|c| c.as_os_str() == "control") && components.next().is_none() {
                     let mut contents = String::new();
                     file.read_to_string(&mut contents).ok()?;
                     return Some(contents);
@@ -164,47 +585,60 @@ pub fn extract_control(f: File) -> Option<String> {
     None
 }
 
-pub fn extract_files_tree(f: File) -> ptree::item::StringItem {
+/// Returns the `_gpgorigin` signature of a signed .deb, `None` if it isn't signed. Only the signature itself is read
+pub fn extract_signature(f: &File) -> std::io::Result<Option<Vec<u8>>> {
+    let mut archive = Archive::new(f);
+
+    while let Some(entry) = archive.next_entry() {
+        let mut entry = entry?;
+
+        if member_name(entry.header()) == "_gpgorigin" {
+            let mut signature = Vec::new();
+            entry.read_to_end(&mut signature)?;
+            return Ok(Some(signature));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Streams the data a `_gpgorigin` signature covers (every other member, concatenated) into `out`
+pub fn write_signed_data(f: &File, out: &mut impl Write) -> std::io::Result<()> {
+    let mut archive = Archive::new(f);
+
+    while let Some(entry) = archive.next_entry() {
+        let mut entry = entry?;
+
+        if member_name(entry.header()) != "_gpgorigin" {
+            std::io::copy(&mut entry, out)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn extract_files_tree(f: File, depth: Option<usize>, limits: Limits) -> std::io::Result<ptree::item::StringItem> {
     let mut archive = Archive::new(f);
 
     let mut builder = TreeBuilder::new("package".to_string());
 
-    while let Some(entry) = archive.next_entry().transpose().expect("ar read fail") {
-        let name = String::from_utf8_lossy(entry.header().identifier())
-            .trim()
-            .trim_end_matches('/')
-            .to_string();
+    while let Some(entry) = archive.next_entry().transpose()? {
+        let name = member_name(entry.header());
         
-        let decoder: Option<Box<dyn Read>> = if name.ends_with(".tar.gz") {
-            Some(Box::new(GzDecoder::new(entry)))
-        } else if name.ends_with(".tar.xz") {
-            Some(Box::new(XzDecoder::new(entry)))
-        } else if name.ends_with(".tar.bz2") {
-            Some(Box::new(BzDecoder::new(entry)))
-        } else if name.ends_with(".tar.zst") {
-            ZstdDecoder::new(entry)
-                .ok()
-                .map(|decoder| Box::new(decoder) as Box<dyn Read>)
-        } else {
-            None
-        };
+        let decoder = decoder(&name, entry, limits.max_uncompressed_size)?;
 
         if let Some(dec) = decoder {
-            let mut subtree = builder.begin_child(name.clone());
+            let subtree = builder.begin_child(name.clone());
             let mut tar = TarArchive::new(dec);
 
-            // Collect all paths first
-            let mut paths = Vec::new();
-            for entry in tar.entries().expect("tar entries fail") {
-                if let Ok(file) = entry {
-                    if let Ok(path) = file.path() {
-                        paths.push(path.display().to_string());
-                    }
-                }
-            }
-            
-            // Build tree from paths
-            build_tree_from_paths(&mut subtree, paths);
+            // paths are added to the tree as they're read, remembering which ones are directories
+            let paths = limited_entries(&mut tar, &name, limits.max_entries)?.map(|entry| {
+                let file = entry?;
+                let is_dir = file.header().entry_type() == EntryType::Directory;
+                Ok((file.path()?.display().to_string(), is_dir))
+            });
+
+            build_tree_from_paths(subtree, paths, depth)?;
             
             builder.end_child();
         } else {
@@ -212,56 +646,258 @@ pub fn extract_files_tree(f: File) -> ptree::item::StringItem {
         }
     }
 
-    builder.build()
+    Ok(builder.build())
 }
 
-fn build_tree_from_paths(builder: &mut TreeBuilder, paths: Vec<String>) {
+fn build_tree_from_paths(builder: &mut TreeBuilder, paths: impl Iterator<Item = std::io::Result<(String, bool)>>, depth: Option<usize>) -> std::io::Result<()> {
     // Build a directory structure
     let mut root: HashMap<String, Node> = HashMap::new();
     
     for path in paths {
+        let (path, is_dir) = path?;
         let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty() && *s != ".").collect();
-        insert_path(&mut root, &parts);
+        insert_path(&mut root, &parts, is_dir);
     }
     
     // Convert to tree
-    add_nodes_to_tree(builder, &root);
+    add_nodes_to_tree(builder, &root, depth);
+
+    Ok(())
 }
 
 #[derive(Default)]
 struct Node {
     children: HashMap<String, Node>,
-    is_file: bool,
+    is_dir: bool,
 }
 
-fn insert_path(node: &mut HashMap<String, Node>, parts: &[&str]) {
+fn insert_path(node: &mut HashMap<String, Node>, parts: &[&str], is_dir: bool) {
     if parts.is_empty() {
         return;
     }
     
     let first = parts[0].to_string();
-    let entry = node.entry(first.clone()).or_insert_with(Node::default);
+    let entry = node.entry(first.clone()).or_default();
     
     if parts.len() == 1 {
-        entry.is_file = true;
+        entry.is_dir |= is_dir;
     } else {
-        insert_path(&mut entry.children, &parts[1..]);
+        // parents are directories even if the archive has no entry for them
+        entry.is_dir = true;
+        insert_path(&mut entry.children, &parts[1..], is_dir);
     }
 }
 
-fn add_nodes_to_tree(builder: &mut TreeBuilder, nodes: &HashMap<String, Node>) {
+// `depth` is how many more levels to render, anything deeper is collapsed into a single "... (N more)" node
+fn add_nodes_to_tree(builder: &mut TreeBuilder, nodes: &HashMap<String, Node>, depth: Option<usize>) {
+    if depth == Some(0) {
+        builder.add_empty_child(format!("... ({} more)", count_nodes(nodes)));
+        return;
+    }
+
+    // directories first, then files, each sorted by name
     let mut sorted_keys: Vec<_> = nodes.keys().collect();
-    sorted_keys.sort();
+    sorted_keys.sort_by_key(|key| (!nodes[*key].is_dir, *key));
     
     for key in sorted_keys {
         let node = &nodes[key];
+        let name = if node.is_dir { format!("{}/", key) } else { key.clone() };
         
         if node.children.is_empty() {
-            builder.add_empty_child(key.clone());
+            builder.add_empty_child(name);
         } else {
-            let mut child = builder.begin_child(key.clone());
-            add_nodes_to_tree(&mut child, &node.children);
+            let child = builder.begin_child(name);
+            add_nodes_to_tree(child, &node.children, depth.map(|d| d - 1));
             builder.end_child();
         }
     }
 }
+
+fn count_nodes(nodes: &HashMap<String, Node>) -> usize {
+    nodes.values().map(|node| 1 + count_nodes(&node.children)).sum()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::{Seek, Write};
+
+    use super::*;
+
+    pub(crate) const NO_LIMITS: Limits = Limits { max_uncompressed_size: u64::MAX, max_entries: u64::MAX };
+
+    /// The modification time of every file `tar` adds
+    pub(crate) const MTIME: u64 = 1_000_000_000;
+
+    /// A tar archive of regular files
+    pub(crate) fn tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(MTIME);
+            header.set_entry_type(EntryType::Regular);
+            builder.append_data(&mut header, path, *contents).expect("Failed to add file to tar");
+        }
+        builder.into_inner().expect("Failed to finish tar")
+    }
+
+    /// An ar member with the identifier exactly as it's written to the header, padded like ar does
+    pub(crate) fn ar_member(identifier: &str, data: &[u8]) -> Vec<u8> {
+        let mut member = format!("{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n", identifier, 0, 0, 0, 100644, data.len()).into_bytes();
+        member.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            member.push(b'\n');
+        }
+        member
+    }
+
+    /// A .deb on disk made of the given members
+    pub(crate) fn deb(members: &[Vec<u8>]) -> File {
+        let mut f = tempfile::tempfile().expect("Failed to create temp file");
+        f.write_all(b"!<arch>\n").expect("Failed to write ar magic");
+        for member in members {
+            f.write_all(member).expect("Failed to write ar member");
+        }
+        f.rewind().expect("Failed to rewind temp file");
+        f
+    }
+
+    pub(crate) fn gz(data: &[u8]) -> Vec<u8> {
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(data).expect("Failed to compress");
+        gz.finish().expect("Failed to compress")
+    }
+
+    // the names of a tree's nodes, children indented below their parent
+    fn names(item: &ptree::item::StringItem, indent: usize, out: &mut Vec<String>) {
+        for child in &item.children {
+            out.push(format!("{}{}", " ".repeat(indent), child.text));
+            names(child, indent + 1, out);
+        }
+    }
+
+    #[test]
+    fn deb_extension_is_case_insensitive() {
+        assert!(is_deb(Path::new("hello.deb")));
+        assert!(is_deb(Path::new("HELLO.DEB")));
+        assert!(is_deb(Path::new("/tmp/hello_1.0_amd64.Deb")));
+        assert!(!is_deb(Path::new("hello")));
+        assert!(!is_deb(Path::new("hello.deb.sig")));
+    }
+
+    #[test]
+    fn tree_lists_directories_before_files() {
+        let mut builder = TreeBuilder::new("package".to_string());
+        let paths = ["./usr/share/doc/b", "./usr/bin/zz", "./usr/a", "./usr/share/a", "./etc/conf", "./README"];
+        build_tree_from_paths(&mut builder, paths.iter().map(|path| Ok((path.to_string(), false))), None).expect("Failed to build tree");
+
+        let mut out = vec![];
+        names(&builder.build(), 0, &mut out);
+        assert_eq!(out, ["etc/", " conf", "usr/", " bin/", "  zz", " share/", "  doc/", "   b", "  a", " a", "README"]);
+    }
+
+    #[test]
+    fn gnu_extended_member_names_are_resolved() {
+        let data = zstd::encode_all(&tar(&[("./usr/bin/hello", b"hi")])[..], 0).expect("Failed to compress data");
+        // GNU ar keeps names in the `//` table and refers to them by offset
+        let f = deb(&[ar_member("//", b"data.tar.zst/\n"), ar_member("debian-binary/", b"2.0\n"), ar_member("/0", &data)]);
+
+        let tree = extract_files_tree(f, None, NO_LIMITS).expect("Failed to read package");
+        let members: Vec<&str> = tree.children.iter().map(|child| child.text.as_str()).collect();
+        assert_eq!(members, ["debian-binary", "data.tar.zst"]);
+        // only a member recognized as a compressed tar gets its files listed
+        assert_eq!(tree.children[1].children[0].text, "usr/");
+    }
+
+    fn member_names(f: File) -> Vec<String> {
+        let mut archive = Archive::new(f);
+        let mut names = Vec::new();
+        while let Some(entry) = archive.next_entry() {
+            names.push(member_name(entry.expect("Failed to read ar member").header()));
+        }
+        names
+    }
+
+    #[test]
+    fn member_names_are_read_in_gnu_and_bsd_spellings() {
+        // GNU ar ends names with `/` and puts long ones in the `//` table
+        let gnu = deb(&[ar_member("//", b"control.tar.gz/\n"), ar_member("debian-binary/", b"2.0\n"), ar_member("/0", b""), ar_member("data.tar.xz/", b"")]);
+        assert_eq!(member_names(gnu), ["debian-binary", "control.tar.gz", "data.tar.xz"]);
+
+        // BSD ar puts the name in front of the data and pads it with NULs
+        let mut bsd_name = b"data.tar.xz".to_vec();
+        bsd_name.resize(16, 0);
+        let bsd = deb(&[ar_member("debian-binary", b"2.0\n"), ar_member("#1/16", &bsd_name)]);
+        assert_eq!(member_names(bsd), ["debian-binary", "data.tar.xz"]);
+
+        // a BSD archive with a GNU style name keeps its `/` in the ar crate
+        let mixed = deb(&[ar_member("#1/16", &bsd_name), ar_member("control.tar.gz/", b"")]);
+        assert_eq!(member_names(mixed), ["data.tar.xz", "control.tar.gz"]);
+    }
+
+    // a .deb whose control.tar.gz holds the given files
+    fn deb_with_control(files: &[(&str, &[u8])]) -> File {
+        deb(&[ar_member("debian-binary/", b"2.0\n"), ar_member("control.tar.gz/", &gz(&tar(files)))])
+    }
+
+    #[test]
+    fn control_is_only_found_at_the_root() {
+        let control = b"Package: hello\n";
+
+        assert_eq!(extract_control(deb_with_control(&[("./control", control)])).as_deref(), Some("Package: hello\n"));
+        assert_eq!(extract_control(deb_with_control(&[("control", control)])).as_deref(), Some("Package: hello\n"));
+        assert_eq!(extract_control(deb_with_control(&[("./foo/control", control), ("./md5sums", b"")])), None);
+        assert_eq!(extract_control(deb_with_control(&[("foo/control", control), ("./control", b"Package: real\n")])).as_deref(), Some("Package: real\n"));
+    }
+
+    // compressed like `zstd --long=<window_log>`, which needs a window that big to decompress
+    fn zstd_long(data: &[u8], window_log: u32) -> Vec<u8> {
+        let mut encoder = zstd::stream::Encoder::new(Vec::new(), 3).expect("Failed to create zstd encoder");
+        encoder.long_distance_matching(true).expect("Failed to enable long distance matching");
+        encoder.window_log(window_log).expect("Failed to set zstd window");
+        encoder.write_all(data).expect("Failed to compress");
+        encoder.finish().expect("Failed to compress")
+    }
+
+    #[test]
+    fn zstd_long_windows_decode() {
+        let data = tar(&[("./usr/bin/hello", b"hi")]);
+
+        // 27 is `zstd --long`'s default and the decoder's default limit, 28 is past it
+        for window_log in [27, 28] {
+            let compressed = zstd_long(&data, window_log);
+            let mut decoder = decoder("data.tar.zst", &compressed[..], u64::MAX)
+                .expect("Failed to set up zstd decoder")
+                .expect("data.tar.zst isn't recognized as compressed");
+            let mut decoded = Vec::new();
+            decoder.read_to_end(&mut decoded).unwrap_or_else(|e| panic!("--long={}: {}", window_log, e));
+            assert_eq!(decoded, data, "--long={}", window_log);
+        }
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn lz4_members_decode() {
+        let data = tar(&[("./usr/bin/hello", b"hi")]);
+        let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+        encoder.write_all(&data).expect("Failed to compress");
+        let compressed = encoder.finish().expect("Failed to compress");
+
+        let mut decoded = Vec::new();
+        decoder("data.tar.lz4", &compressed[..], u64::MAX)
+            .expect("Failed to set up lz4 decoder")
+            .expect("data.tar.lz4 isn't recognized as compressed")
+            .read_to_end(&mut decoded).expect("Failed to decompress");
+        assert_eq!(decoded, data);
+    }
+
+    #[cfg(not(feature = "lz4"))]
+    #[test]
+    fn lz4_members_are_unsupported_without_the_feature() {
+        let Err(e) = decoder("data.tar.lz4", &b""[..], u64::MAX) else {
+            panic!("data.tar.lz4 got a decoder without the lz4 feature");
+        };
+        assert_eq!(e.kind(), std::io::ErrorKind::Unsupported);
+    }
+}
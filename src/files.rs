@@ -0,0 +1,85 @@
+use std::{io::Write, path::Path};
+
+use clio::Output;
+use log::{error, info};
+use serde::Serialize;
+use sqlite3::{Connection, State};
+
+use crate::{control::ControlWithData, install, output::{self, Format}};
+
+#[derive(Serialize)]
+struct Files<'a> {
+    package: &'a str,
+    files: Vec<&'a str>,
+}
+
+#[derive(Serialize)]
+struct Owner<'a> {
+    path: &'a str,
+    package: Option<&'a str>,
+}
+
+/// Lists the files an installed package added
+pub fn files(conn: Connection, package: String, format: Format, out: &mut Output) {
+    let installed = match ControlWithData::latest(&conn, &package) {
+        Ok(installed) => installed,
+        Err(_) => {
+            error!("{} is not installed", package);
+            std::process::exit(1);
+        }
+    };
+
+    let files: Vec<&str> = installed.installed.split(',').filter(|s| !s.is_empty()).collect();
+
+    match format {
+        Format::Text => {
+            for path in files {
+                writeln!(out, "{}", path).expect("Failed to write file list");
+            }
+        },
+        Format::Json => output::write_json(out, &Files { package: &installed.ctrl.package, files }),
+        Format::Null => {
+            for path in files {
+                write!(out, "{}\0", path).expect("Failed to write file list");
+            }
+        },
+    }
+}
+
+/// The installed package that added `path`, which has to be absolute and normalized like the recorded paths
+pub fn owning_package(conn: &Connection, path: &str) -> Result<Option<String>, sqlite3::Error> {
+    let mut stmt = conn.prepare("SELECT package, installed FROM debs ORDER BY id DESC")?;
+
+    while stmt.next()? == State::Row {
+        let installed = stmt.read::<String>(1).unwrap_or_default();
+
+        if installed.split(',').any(|installed| installed == path) {
+            return Ok(stmt.read::<String>(0).ok());
+        }
+    }
+
+    Ok(None)
+}
+
+/// Finds the installed package that added `path`, returns whether one did
+pub fn owner(conn: Connection, path: &Path, format: Format, out: &mut Output) -> bool {
+    // paths are stored absolute and normalized
+    let path = std::env::current_dir().map(|cwd| cwd.join(path)).unwrap_or(path.to_path_buf());
+    let path = install::normalize_path(&path).display().to_string();
+
+    let package = owning_package(&conn, &path).expect("Failed to look up owner");
+
+    match format {
+        Format::Text => match &package {
+            Some(package) => writeln!(out, "{}: {}", path, package).expect("Failed to write owner"),
+            None => info!("{} isn't owned by any package", path),
+        },
+        Format::Json => output::write_json(out, &Owner { path: &path, package: package.as_deref() }),
+        Format::Null => match &package {
+            Some(package) => write!(out, "{}\0{}\0", path, package).expect("Failed to write owner"),
+            None => info!("{} isn't owned by any package", path),
+        },
+    }
+
+    package.is_some()
+}
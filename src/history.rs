@@ -0,0 +1,104 @@
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, TimeDelta};
+use cli_table::{Cell, Style, Table};
+use clio::Output;
+use log::info;
+use sqlite3::{Connection, State};
+
+use crate::{control, view};
+
+pub const INSTALL: &str = "install";
+pub const UNINSTALL: &str = "uninstall";
+pub const REINSTALL: &str = "reinstall";
+
+pub fn record(conn: &Connection, operation: &str, package: &str, version: &str, file_count: usize) -> Result<(), sqlite3::Error> {
+    let mut stmt = conn.prepare("INSERT INTO history (operation, package, version, file_count) VALUES (?, ?, ?, ?)")?;
+    stmt.bind(1, operation)?;
+    stmt.bind(2, package)?;
+    stmt.bind(3, version)?;
+    stmt.bind(4, file_count as i64)?;
+    stmt.next()?;
+
+    Ok(())
+}
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+// `2024-01-31` or `2024-01-31 12:00:00` (a `T` works as the separator too), the bool is whether it had a time
+fn parse_time(s: &str) -> Result<(NaiveDateTime, bool), String> {
+    let s = s.trim();
+
+    if let Ok(time) = NaiveDateTime::parse_from_str(&s.replacen('T', " ", 1), TIMESTAMP_FORMAT) {
+        return Ok((time, true));
+    }
+
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|date| (date.and_time(NaiveTime::MIN), false))
+        .map_err(|_| format!("expected YYYY-MM-DD or \"YYYY-MM-DD HH:MM:SS\", got {:?}", s))
+}
+
+/// Parses `--since`, the start of the range
+pub fn parse_since(s: &str) -> Result<NaiveDateTime, String> {
+    parse_time(s).map(|(time, _)| time)
+}
+
+/// Parses `--until` into the (exclusive) end of the range, so a date includes that whole day
+pub fn parse_until(s: &str) -> Result<NaiveDateTime, String> {
+    let (time, has_time) = parse_time(s)?;
+    Ok(if has_time { time + TimeDelta::seconds(1) } else { time + TimeDelta::days(1) })
+}
+
+pub fn history(conn: Connection, since: Option<NaiveDateTime>, until: Option<NaiveDateTime>, package: Option<String>, out: &mut Output) {
+    let mut conditions = vec![];
+    let mut params = vec![];
+
+    // timestamps are stored as `YYYY-MM-DD HH:MM:SS`, which sorts the same as the time it represents
+    if let Some(since) = since {
+        conditions.push("timestamp >= ?");
+        params.push(since.format(TIMESTAMP_FORMAT).to_string());
+    }
+    if let Some(until) = until {
+        conditions.push("timestamp < ?");
+        params.push(until.format(TIMESTAMP_FORMAT).to_string());
+    }
+    if let Some(package) = &package {
+        conditions.push("lower(package) = ?");
+        params.push(control::normalize_package_name(package));
+    }
+
+    let filter = if conditions.is_empty() { String::new() } else { format!("WHERE {}", conditions.join(" AND ")) };
+    let mut stmt = conn.prepare(format!("SELECT timestamp, operation, package, version, file_count FROM history {} ORDER BY id", filter))
+        .expect("Failed to prepare statement");
+    for (i, param) in params.iter().enumerate() {
+        stmt.bind(i + 1, param.as_str()).expect("Failed to bind filter");
+    }
+
+    let mut rows = vec![];
+
+    while stmt.next().expect("Failed to get row") == State::Row {
+        let row = (0..stmt.columns())
+            .map(|i| stmt.read::<String>(i).unwrap_or_default().cell())
+            .collect::<Vec<_>>();
+
+        rows.push(row);
+    }
+
+    if rows.is_empty() {
+        if params.is_empty() {
+            info!("No operations recorded yet.");
+        } else {
+            info!("No operations match.");
+        }
+        return;
+    }
+
+    let table = rows.table()
+        .title(vec![
+            "date (UTC)".cell().bold(true),
+            "operation".cell().bold(true),
+            "package".cell().bold(true),
+            "version".cell().bold(true),
+            "files".cell().bold(true),
+        ]);
+
+    view::print_table(out, table);
+}
@@ -1,44 +1,127 @@
-use std::{collections::HashMap, fs::File, path::{Path, PathBuf}};
+use std::{collections::{BTreeSet, HashMap, HashSet}, fs::File, os::unix::fs::{MetadataExt, PermissionsExt}, io::{Seek, Write}, path::{Component, Path, PathBuf}, process::{Command, Stdio}, str::FromStr};
 
-use clio::ClioPath;
+use cli_table::{Cell, Style, Table};
+use clio::{ClioPath, Output};
 use colored::Colorize;
-use directories::ProjectDirs;
-use log::{error, info, warn};
+use indicatif::ProgressBar;
+use log::{debug, error, info, warn};
 use sqlite3::{Connection, State, Value};
+use tar::EntryType;
 use walkdir::WalkDir;
 
-use crate::{control::{self, ControlWithData}, extract, view};
+use crate::{backup, control::{self, ControlWithData, LookupError}, deps::{self, AssumeInstalled}, files, interrupt, pin::{self, Pins}, extract::{self, MixedEta, Limits, ProgressUnit}, history, prompt::Prompter, summary::Summary, view};
+
+/// Rewrites destination paths starting with `from` to start with `to` instead (`--prefix-map from=to`)
+#[derive(Clone, Debug)]
+pub struct PrefixMap {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+impl FromStr for PrefixMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s.split_once('=').ok_or("expected <from>=<to>")?;
+        let (from, to) = (PathBuf::from(from), PathBuf::from(to));
+
+        if !from.is_absolute() || !to.is_absolute() {
+            return Err("both prefixes must be absolute paths".to_string());
+        }
+
+        Ok(PrefixMap { from, to })
+    }
+}
+
+impl PrefixMap {
+    fn apply(&self, path: &Path) -> Option<PathBuf> {
+        path.strip_prefix(&self.from).ok().map(|rest| self.to.join(rest))
+    }
+}
+
+pub struct InstallOptions {
+    pub verbose: bool,
+    pub quiet: bool,
+    pub progress_unit: ProgressUnit,
+    pub progress_template: Option<String>,
+    pub limits: Limits,
+    pub assume_installed: Vec<AssumeInstalled>,
+    pub prefix_map: Vec<PrefixMap>,
+    pub require_signature: bool,
+    pub keyring: Option<PathBuf>,
+    pub dereference: bool,
+    pub no_setuid: bool,
+    pub safe: bool,
+    pub allow_dangerous: bool,
+    pub deny_paths: Vec<PathBuf>,
+    pub no_overwrite_newer: bool,
+    pub force_overwrite: bool,
+    pub no_register: bool,
+    pub verify: bool,
+    pub reinstall: bool,
+    pub pins: Pins,
+    pub simulate: bool,
+    pub prompter: Prompter,
+}
+
+pub fn install(deb: ClioPath, cache_dir: &Path, conn: Connection, opts: InstallOptions) {
+    let InstallOptions { verbose, quiet, progress_unit, progress_template, limits, assume_installed, prefix_map, require_signature, keyring, dereference, no_setuid, safe, allow_dangerous, deny_paths, no_overwrite_newer, force_overwrite, no_register, verify, reinstall, pins, simulate, prompter } = opts;
 
-pub fn install(deb: ClioPath, dirs: ProjectDirs, conn: Connection, verbose: bool) {
     if !deb.exists() {
         error!("Failed to install .deb file because the .deb file you specified does not exist.");
         std::process::exit(-1);
     }
 
-    if deb.extension().is_none_or(|ext| ext != "deb") {
+    if !extract::is_deb(&deb) {
         error!("Failed to install .deb file because the file you specified isn't one.");
         std::process::exit(-1);
     }
 
-    let f = File::open(deb.to_path_buf()).unwrap();
+    extract::check_members(&deb);
 
-    let cache_dir = dirs.cache_dir();
     let extract_dir = cache_dir.join("extracted");
 
     let _ = std::fs::remove_dir_all(&extract_dir);
 
-    extract::extract_to(extract_dir.clone(), f);
+    match verify_signature(extract::open(&deb), cache_dir, keyring.as_deref()) {
+        Ok(true) => info!("Package signature is valid"),
+        Ok(false) if require_signature => {
+            error!("Refusing to install an unsigned package because --require-signature was passed");
+            std::process::exit(1);
+        },
+        Ok(false) => {},
+        Err(e) if require_signature => {
+            error!("Refusing to install package: {}", e);
+            std::process::exit(1);
+        },
+        Err(e) => warn!("{}, installing anyway", e),
+    }
+
+    // files are streamed straight to where they get installed, only --dereference needs the
+    // whole package unpacked in the cache first so it can follow symlinks inside of it
+    let (ctrl_str, paths, data_bytes) = if dereference {
+        extract::extract_to(extract_dir.clone(), extract::open(&deb), quiet, progress_unit, progress_template.as_deref(), limits)
+            .unwrap_or_else(|e| extract::extract_failed(&deb, e));
 
-    let ctrl_path = extract_dir.join("control").join("control");
+        let ctrl_path = extract_dir.join("control").join("control");
+        let ctrl_str = ctrl_path.is_file().then(|| std::fs::read_to_string(ctrl_path).expect("Failed to read control file"));
 
-    if !ctrl_path.is_file() {
+        (ctrl_str, extracted_paths(&extract_dir), 0)
+    } else {
+        let spinner = extract::spinner("Reading package contents...", quiet);
+        let ctrl_str = extract::extract_control(extract::open(&deb));
+        let (paths, data_bytes) = extract::data_paths_with_size(extract::open(&deb), limits)
+            .unwrap_or_else(|e| extract::extract_failed(&deb, e));
+        spinner.finish_and_clear();
+
+        (ctrl_str, paths, data_bytes)
+    };
+
+    let Some(ctrl_str) = ctrl_str else {
         error!("Failed to get control file from .deb, make sure the .deb is valid");
         std::process::exit(-1);
-    }
-
-    let installed = copy(extract_dir, verbose);
+    };
 
-    let ctrl_str = std::fs::read_to_string(ctrl_path).expect("Failed to read control file");
     let ctrl = match control::parse_control(ctrl_str) {
         Ok(ctrl) => ctrl,
         Err(e) => {
@@ -47,25 +130,561 @@ pub fn install(deb: ClioPath, dirs: ProjectDirs, conn: Connection, verbose: bool
         }
     };
 
+    if let Some(problem) = ctrl.maintainer_problem() {
+        warn!("Malformed Maintainer field {:?}: {}", ctrl.maintainer, problem);
+    }
+
+    if verify {
+        // --dereference didn't add up the sizes while listing the files
+        let data_bytes = if dereference {
+            extract::data_paths_with_size(extract::open(&deb), limits)
+                .unwrap_or_else(|e| extract::extract_failed(&deb, e))
+                .1
+        } else {
+            data_bytes
+        };
+        if let Some(problem) = ctrl.installed_size_problem(data_bytes, paths.len()) {
+            warn!("Installed-Size looks wrong, the package may be malformed or repacked: {}", problem);
+        }
+    }
+
+    let mut problems = 0;
+
+    match deps::unmet_depends(&conn, &ctrl, &assume_installed) {
+        Ok(unmet) => {
+            for group in &unmet {
+                let alternatives = group.iter().map(|rel| rel.to_string()).collect::<Vec<_>>();
+                warn!("Unmet dependency: {}", alternatives.join(" | "));
+            }
+            problems += unmet.len();
+        },
+        Err(e) => warn!("Failed to check dependencies: {}", e),
+    }
+
+    let conflicting = match deps::conflicts(&conn, &ctrl) {
+        Ok(conflicts) => {
+            problems += conflicts.len();
+            for (pkg, rel) in &conflicts {
+                warn!("{} conflicts with installed package {} ({})", ctrl.package, pkg, rel);
+            }
+            conflicts.into_iter().map(|(pkg, _)| pkg).collect()
+        },
+        Err(e) => {
+            warn!("Failed to check conflicts: {}", e);
+            vec![]
+        },
+    };
+
+    problems += pin::check(&conn, &ctrl, &conflicting, &pins);
+
+    if problems > 0 && !simulate && !prompter.confirm(&format!("Install {} anyway?", ctrl.package), true) {
+        error!("Nothing was installed");
+        std::process::exit(1);
+    }
+
+    // metapackages only exist to pull in their dependencies, they're still recorded so those can be tracked
+    if paths.iter().all(|(_, is_dir)| *is_dir) {
+        info!("{} is a metapackage: no files to install", ctrl.package);
+    }
+
+    let dangerous = dangerous_paths(&paths, &prefix_map, &deny_paths);
+    for path in &dangerous {
+        warn!("{} would write to sensitive path {}", ctrl.package, path.display());
+    }
+    if !dangerous.is_empty() && safe && !allow_dangerous {
+        error!("Refusing to write to {} sensitive path(s) because --safe was passed, pass --allow-dangerous to install anyway", dangerous.len());
+        std::process::exit(1);
+    }
+
+    let unowned = unowned_files(&conn, &paths, &prefix_map).unwrap_or_else(|e| {
+        warn!("Failed to check for files that weren't installed by debby: {}", e);
+        vec![]
+    });
+    for path in &unowned {
+        warn!("{} already exists and wasn't installed by debby", path.display());
+    }
+    if !unowned.is_empty() && !force_overwrite {
+        error!("Refusing to overwrite {} file(s), pass --force-overwrite to back them up to <file>.debby-orig and install anyway", unowned.len());
+        std::process::exit(1);
+    }
+    if let Some(backup) = unowned.iter().map(|path| backup::backup_path(path)).find(|backup| backup.symlink_metadata().is_ok()) {
+        error!("Refusing to back up over {}, move it somewhere else first", backup.display());
+        std::process::exit(1);
+    }
+
+    // the rows of the same version get replaced once the package is installed again
+    let reinstalled = if reinstall { installed_ids(&conn, &ctrl.package, Some(&ctrl.version)) } else { vec![] };
+    if reinstall && reinstalled.is_empty() {
+        info!("{} {} isn't installed, installing it normally", ctrl.package, ctrl.version);
+    }
+    let stale = stale_paths(&conn, &reinstalled, &paths, &prefix_map);
+
+    if simulate {
+        print_install_plan(&paths, &prefix_map, &ctrl, &unowned, &stale, !reinstalled.is_empty(), !no_register);
+        return;
+    }
+
+    if let Err((dir, e)) = check_writable(&paths, &prefix_map) {
+        error!("Can't write to {}: {}, nothing was installed", dir.display(), e);
+        std::process::exit(1);
+    }
+
+    // what's on disk before this point is untouched, so Ctrl-C only has to be handled from here on
+    let owned = owned_paths(&conn).unwrap_or_default();
+    interrupt::catch();
+
+    if let Err(e) = backup::backup(&unowned) {
+        error!("{}, nothing was installed", e);
+        std::process::exit(1);
+    }
+
+    let write_opts = WriteOptions { verbose, prefix_map: &prefix_map, no_setuid, no_overwrite_newer, deny_paths: &deny_paths, refuse_dangerous: safe && !allow_dangerous };
+    let mut summary = Summary::new("files/directories");
+    let mut failed = false;
+    let installed = if dereference {
+        copy(extract_dir, dereference, &write_opts, &mut summary)
+    } else {
+        let total = match progress_unit {
+            _ if quiet => 0,
+            ProgressUnit::Files => paths.len() as u64,
+            ProgressUnit::Bytes => data_bytes,
+        };
+
+        let eta = MixedEta::new(data_bytes);
+        let bar = extract::progress_bar(total, progress_unit, progress_template.as_deref(), quiet, &eta);
+
+        match stream(extract::open(&deb), bar, &eta, progress_unit, limits, &write_opts, &mut summary) {
+            Ok(installed) => installed,
+            Err((e, installed)) if e.kind() == std::io::ErrorKind::Interrupted => installed,
+            Err((e, installed)) => {
+                // whatever made it to disk is still recorded below, so it can be uninstalled again
+                error!("Failed to extract {}: {}, the package is only partially installed", deb.display(), e);
+                failed = true;
+                installed
+            },
+        }
+    };
+
+    if interrupt::interrupted() {
+        roll_back(&ctrl, &installed, &owned, &unowned);
+    }
+
+    if no_register {
+        warn!("{} was not recorded because --no-register was passed, debby can't uninstall its files", ctrl.package);
+        for path in &unowned {
+            warn!("{} won't be restored from {} automatically", path.display(), backup::backup_path(path).display());
+        }
+        summary.print(&format!("Installed the files of {} {}", ctrl.package, ctrl.version), failed);
+        if failed {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // a partial reinstall keeps the old rows, so the files they recorded can still be uninstalled
+    if failed && !reinstalled.is_empty() {
+        warn!("Kept the previous install of {} {} in the database since the reinstall failed", ctrl.package, ctrl.version);
+    }
+    let replaced = if failed { vec![] } else { reinstalled };
+    if !replaced.is_empty() {
+        for path in &stale {
+            match std::fs::remove_file(path) {
+                Ok(()) => debug!("{}: removed {}", ctrl.package, path.display()),
+                Err(e) => warn!("Failed to remove file/symlink {}: {}", path.display(), e),
+            }
+        }
+        if !stale.is_empty() {
+            info!("Removed {} files {} no longer ships", stale.len(), ctrl.package);
+        }
+    }
+
     let (cols, vals) = ctrl.populate_sql();
 
-    let stmt = &format!(
-        "INSERT INTO debs ({}, installed) VALUES ({}, '{}')",
+    // the file list is bound, a path with a `'` in it would break the statement otherwise
+    let stmt = format!(
+        "INSERT INTO debs ({}, description_short, description_long, installed) VALUES ({}, {}, {}, ?)",
         cols,
         vals,
-        installed
+        control::sql_value(Some(ctrl.synopsis())),
+        control::sql_value(ctrl.long_description())
     );
 
-    conn.execute(
-        stmt
-    ).expect("Failed to insert deb");
+    let file_count = installed.split(',').filter(|s| !s.is_empty()).count();
+
+    conn.execute("BEGIN").expect("Failed to begin transaction");
+    let mut insert_stmt = conn.prepare(stmt).expect("Failed to prepare INSERT statement");
+    insert_stmt.bind(1, installed.as_str()).expect("Failed to bind installed files to INSERT statement");
+    insert_stmt.next().expect("Failed to insert deb");
+    let deb_id = last_insert_id(&conn).expect("Failed to get the id of the inserted deb");
+    for path in &unowned {
+        backup::record(&conn, deb_id, path).expect("Failed to record backup");
+    }
+    for &old_id in &replaced {
+        backup::reassign(&conn, old_id, deb_id).expect("Failed to move backups to the reinstalled deb");
+
+        let mut delete_stmt = conn.prepare("DELETE FROM debs WHERE id = ?").expect("Failed to prepare DELETE statement");
+        delete_stmt.bind(1, old_id).expect("Failed to bind id to DELETE statement");
+        delete_stmt.next().expect("Failed to run DELETE statement");
+    }
+    let operation = if replaced.is_empty() { history::INSTALL } else { history::REINSTALL };
+    history::record(&conn, operation, &ctrl.package, &ctrl.version, file_count).expect("Failed to record history");
+    if interrupt::interrupted() {
+        conn.execute("ROLLBACK").expect("Failed to roll back transaction");
+        roll_back(&ctrl, &installed, &owned, &unowned);
+    }
+    conn.execute("COMMIT").expect("Failed to commit transaction");
+
+    for path in installed.split(',').filter(|s| !s.is_empty()) {
+        debug!("{}: added {}", ctrl.package, path);
+    }
+    let headline = if replaced.is_empty() { "Installed" } else { "Reinstalled" };
+    summary.print(&format!("{} {} {}", headline, ctrl.package, ctrl.version), failed);
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+// undoes an install that Ctrl-C cut short: removes the files and symlinks it added that no other package
+// (or an earlier install of this one) owns, and puts back the files it backed up. Directories are left alone
+fn roll_back(ctrl: &control::Control, installed: &str, owned: &HashSet<String>, unowned: &[PathBuf]) -> ! {
+    warn!("Interrupted, rolling back...");
+
+    for path in installed.split(',').filter(|s| !s.is_empty() && !owned.contains(*s)) {
+        let is_dir = std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_dir());
+        if !is_dir && let Err(e) = std::fs::remove_file(path) {
+            warn!("Failed to remove file/symlink {}: {}", path, e);
+        }
+    }
+    backup::undo(unowned);
+
+    error!("Interrupted, {} {} was not installed", ctrl.package, ctrl.version);
+    // 128 + SIGINT, like a shell reports a process killed by Ctrl-C
+    std::process::exit(130);
+}
+
+// everything an install would do, without touching the filesystem or the database
+fn print_install_plan(paths: &[(PathBuf, bool)], prefix_map: &[PrefixMap], ctrl: &control::Control, unowned: &[PathBuf], stale: &[PathBuf], reinstall: bool, register: bool) {
+    info!("Would {} {} {}:", if reinstall { "reinstall" } else { "install" }, ctrl.package, ctrl.version);
+    for path in unowned {
+        info!("  back up {} to {}", path.display(), backup::backup_path(path).display());
+    }
+    for (path, _) in paths {
+        info!("  add {}", destination(path, prefix_map).display());
+    }
+    for path in stale {
+        info!("  remove {}", path.display());
+    }
+    if reinstall {
+        info!("  replace {} {} in the database and record it in the history", ctrl.package, ctrl.version);
+    } else if register {
+        info!("  insert {} {} into the database and record it in the history", ctrl.package, ctrl.version);
+    }
+    info!("  no maintainer scripts would be run");
 }
 
-pub fn copy(extract_dir: PathBuf, verbose: bool) -> String {
+/// Verifies the `_gpgorigin` signature of a .deb with gpgv, returns `Ok(false)` if the package isn't signed
+fn verify_signature(f: File, cache_dir: &Path, keyring: Option<&Path>) -> Result<bool, String> {
+    let signature = match extract::extract_signature(&f) {
+        Ok(Some(signature)) => signature,
+        Ok(None) => return Ok(false),
+        Err(e) => return Err(format!("Failed to read package signature: {}", e)),
+    };
+
+    let _ = std::fs::create_dir_all(cache_dir);
+    let sig_path = cache_dir.join("_gpgorigin");
+    std::fs::write(&sig_path, signature).map_err(|e| format!("Failed to write package signature: {}", e))?;
+
+    let mut cmd = Command::new("gpgv");
+    if let Some(keyring) = keyring {
+        cmd.arg("--keyring").arg(keyring);
+    }
+
+    let child = cmd.arg(&sig_path)
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn();
+
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => return Err(format!("Failed to run gpgv to verify the package signature: {}", e)),
+    };
+
+    // gpgv may exit early without reading everything, the exit status tells us what happened.
+    // failing to read the package is our problem though
+    let mut stdin = child.stdin.take().expect("Failed to open gpgv stdin");
+    let streamed = (&f).rewind().and_then(|_| extract::write_signed_data(&f, &mut stdin));
+    drop(stdin);
+
+    if let Err(e) = streamed
+        && e.kind() != std::io::ErrorKind::BrokenPipe
+    {
+        let _ = child.kill();
+        let _ = child.wait();
+        let _ = std::fs::remove_file(&sig_path);
+        return Err(format!("Failed to read the signed package data: {}", e));
+    }
+
+    let output = child.wait_with_output().map_err(|e| format!("Failed to wait for gpgv: {}", e))?;
+    let _ = std::fs::remove_file(&sig_path);
+
+    if output.status.success() {
+        Ok(true)
+    } else {
+        Err(format!("Package signature is invalid: {}", String::from_utf8_lossy(&output.stderr).trim()))
+    }
+}
+
+/// Lexically normalizes a path (no symlink resolution) so `/usr/bin`, `/usr/bin/` and `/usr/./bin` compare equal
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                normalized.pop();
+            },
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+// where a path relative to the package root gets installed to
+fn destination(rel: &Path, prefix_map: &[PrefixMap]) -> PathBuf {
+    prefix_map.iter().fold(Path::new("/").join(rel), |dest, map| map.apply(&dest).unwrap_or(dest))
+}
+
+// everything unpacked into the cache's data dir, relative to the package root, and whether it's a directory
+fn extracted_paths(extract_dir: &Path) -> Vec<(PathBuf, bool)> {
+    let data_dir = extract_dir.join("data");
+
+    WalkDir::new(&data_dir).min_depth(1).into_iter()
+        .filter_map(|e| e.ok())
+        .map(|entry| (entry.path().strip_prefix(&data_dir).unwrap().to_path_buf(), entry.file_type().is_dir()))
+        .collect()
+}
+
+/// Makes sure every directory the package installs into is writable before copying anything,
+/// so a read-only mount doesn't leave a half-installed package behind
+pub fn check_writable(paths: &[(PathBuf, bool)], prefix_map: &[PrefixMap]) -> Result<(), (PathBuf, std::io::Error)> {
+    let mut dirs = BTreeSet::new();
+
+    for (path, _) in paths {
+        let dest = destination(path, prefix_map);
+
+        // the closest directory that already exists is the one we need to be able to write to
+        if let Some(existing) = dest.ancestors().skip(1).find(|dir| dir.is_dir()) {
+            dirs.insert(existing.to_path_buf());
+        }
+    }
+
+    for dir in dirs {
+        let probe = dir.join(format!(".debby-write-test-{}", std::process::id()));
+
+        if let Err(e) = std::fs::OpenOptions::new().write(true).create_new(true).open(&probe) {
+            return Err((dir, e));
+        }
+        let _ = std::fs::remove_file(&probe);
+    }
+
+    Ok(())
+}
+
+/// Paths a package should never need to touch, on top of whatever is passed with `--deny-path`
+pub const DENYLIST: &[&str] = &[
+    "/etc/sudoers",
+    "/etc/sudoers.d",
+    "/etc/passwd",
+    "/etc/shadow",
+    "/etc/group",
+    "/etc/gshadow",
+    "/etc/pam.d",
+    "/etc/ld.so.preload",
+    "/root",
+    "/boot",
+    "/bin/sh",
+    "/bin/bash",
+    "/bin/su",
+    "/usr/bin/sh",
+    "/usr/bin/bash",
+    "/usr/bin/su",
+    "/usr/bin/sudo",
+];
+
+/// Returns the files the package would install into a denylisted path (or anywhere below one)
+pub fn dangerous_paths(paths: &[(PathBuf, bool)], prefix_map: &[PrefixMap], deny_paths: &[PathBuf]) -> Vec<PathBuf> {
+    paths.iter()
+        .filter(|(_, is_dir)| !is_dir)
+        .map(|(path, _)| normalize_path(&destination(path, prefix_map)))
+        // a symlinked directory that's already there can lead somewhere else than the path says
+        .filter(|dest| is_dangerous(dest, deny_paths) || is_dangerous(&resolve_parent(dest), deny_paths))
+        .collect()
+}
+
+fn is_dangerous(dest: &Path, deny_paths: &[PathBuf]) -> bool {
+    DENYLIST.iter()
+        .map(PathBuf::from)
+        .chain(deny_paths.iter().map(|path| normalize_path(path)))
+        .any(|denied| dest.starts_with(denied))
+}
+
+/// Returns the files the package would overwrite that no installed package owns, i.e. ones debby didn't put there
+pub fn unowned_files(conn: &Connection, paths: &[(PathBuf, bool)], prefix_map: &[PrefixMap]) -> Result<Vec<PathBuf>, sqlite3::Error> {
+    let owned = owned_paths(conn)?;
+
+    Ok(paths.iter()
+        .filter(|(_, is_dir)| !is_dir)
+        .map(|(path, _)| normalize_path(&destination(path, prefix_map)))
+        .filter(|dest| dest.symlink_metadata().is_ok_and(|metadata| !metadata.is_dir()))
+        .filter(|dest| !owned.contains(&dest.display().to_string()))
+        .collect())
+}
+
+// the files and symlinks recorded by the `deb_ids` rows that the package doesn't ship anymore
+fn stale_paths(conn: &Connection, deb_ids: &[i64], paths: &[(PathBuf, bool)], prefix_map: &[PrefixMap]) -> Vec<PathBuf> {
+    let shipped: HashSet<PathBuf> = paths.iter().map(|(path, _)| normalize_path(&destination(path, prefix_map))).collect();
+    let mut stale = BTreeSet::new();
+
+    for &deb_id in deb_ids {
+        let mut stmt = conn.prepare("SELECT installed FROM debs WHERE id = ?").expect("Failed to prepare statement");
+        stmt.bind(1, deb_id).expect("Failed to bind id");
+        if stmt.next().expect("Failed to get row") != State::Row {
+            continue;
+        }
+
+        let installed = stmt.read::<String>(0).unwrap_or_default();
+        stale.extend(installed.split(',')
+            .filter(|s| !s.is_empty())
+            .map(|s| normalize_path(Path::new(s.trim())))
+            .filter(|path| !shipped.contains(path))
+            .filter(|path| std::fs::symlink_metadata(path)
+                .is_ok_and(|metadata| metadata.file_type().is_file() || metadata.file_type().is_symlink())));
+    }
+
+    stale.into_iter().collect()
+}
+
+// every path recorded by any installed package
+fn owned_paths(conn: &Connection) -> Result<HashSet<String>, sqlite3::Error> {
+    let mut owned = HashSet::new();
+
+    let mut stmt = conn.prepare("SELECT installed FROM debs")?;
+    while stmt.next()? == State::Row {
+        let installed = stmt.read::<String>(0).unwrap_or_default();
+        owned.extend(installed.split(',').filter(|s| !s.is_empty()).map(|s| s.trim().to_string()));
+    }
+
+    Ok(owned)
+}
+
+// follows a symlink inside the extracted package, absolute targets are resolved relative to the package root
+fn resolve_in_package(data_dir: &Path, link: &Path) -> Option<PathBuf> {
+    let mut current = link.to_path_buf();
+
+    // same limit as the kernel's ELOOP
+    for _ in 0..40 {
+        let target = std::fs::read_link(&current).ok()?;
+        let resolved = match target.strip_prefix("/") {
+            Ok(rel) => data_dir.join(rel),
+            Err(_) => current.parent()?.join(target),
+        };
+        let resolved = normalize_path(&resolved);
+
+        if !resolved.starts_with(data_dir) {
+            return None;
+        }
+
+        match resolved.symlink_metadata() {
+            Ok(meta) if meta.file_type().is_symlink() => current = resolved,
+            Ok(_) => return Some(resolved),
+            Err(_) => return None,
+        }
+    }
+
+    None
+}
+
+/// How `copy` and `stream` write files
+pub struct WriteOptions<'a> {
+    pub verbose: bool,
+    pub prefix_map: &'a [PrefixMap],
+    pub no_setuid: bool,
+    pub no_overwrite_newer: bool,
+    pub deny_paths: &'a [PathBuf],
+    pub refuse_dangerous: bool,
+}
+
+impl WriteOptions<'_> {
+    // with --no-overwrite-newer, files modified after the package's version of them are left alone.
+    // only regular files count, a symlink's mtime is just when it was created
+    fn keep_existing(&self, dest: &Path, mtime: i64) -> bool {
+        let newer = self.no_overwrite_newer && dest.symlink_metadata()
+            .is_ok_and(|metadata| metadata.is_file() && metadata.mtime() > mtime);
+
+        if newer {
+            info!("Keeping {}, it's newer than the one in the package", dest.display());
+        }
+
+        newer
+    }
+
+    // writing `dest` mustn't go through a symlink the package created itself (`lnk -> /etc`, then `lnk/passwd`),
+    // or through any symlink that leads out of the root `dest` was mapped to. Where it really ends up gets the
+    // sensitive path check too, the one before installing only saw the paths in the package
+    fn check_parent(&self, dest: &Path, links: &HashSet<PathBuf>) -> Result<(), String> {
+        if let Some(link) = dest.ancestors().skip(1).find(|ancestor| links.contains(*ancestor)) {
+            return Err(format!("{} is a symlink the package created", link.display()));
+        }
+
+        let resolved = resolve_parent(dest);
+        let root = self.prefix_map.iter()
+            .map(|map| map.to.as_path())
+            .filter(|to| dest.starts_with(to))
+            .max_by_key(|to| to.components().count())
+            .unwrap_or(Path::new("/"));
+        let root = root.canonicalize().unwrap_or(root.to_path_buf());
+        if !resolved.starts_with(&root) {
+            return Err(format!("it resolves to {}, outside of {}", resolved.display(), root.display()));
+        }
+
+        if resolved != dest && is_dangerous(&resolved, self.deny_paths) {
+            if self.refuse_dangerous {
+                return Err(format!("it resolves to sensitive path {}", resolved.display()));
+            }
+            warn!("{} resolves to sensitive path {}", dest.display(), resolved.display());
+        }
+
+        Ok(())
+    }
+}
+
+// `path` with the symlinks in its existing parent directories resolved, the rest can't be a symlink yet
+fn resolve_parent(path: &Path) -> PathBuf {
+    let Some(parent) = path.parent() else { return path.to_path_buf() };
+
+    match parent.ancestors().find(|ancestor| ancestor.symlink_metadata().is_ok()) {
+        Some(existing) => match existing.canonicalize() {
+            Ok(resolved) => resolved.join(path.strip_prefix(existing).expect("an ancestor is a prefix")),
+            Err(_) => path.to_path_buf(),
+        },
+        None => path.to_path_buf(),
+    }
+}
+
+pub fn copy(extract_dir: PathBuf, dereference: bool, opts: &WriteOptions, summary: &mut Summary) -> String {
+    let WriteOptions { verbose, prefix_map, no_setuid, .. } = *opts;
     let mut copied_files: Vec<PathBuf> = vec![];
     let data_dir = extract_dir.join("data");
 
     for entry in WalkDir::new(&data_dir).into_iter().filter_map(|e| e.ok()) {
+        if interrupt::interrupted() {
+            break;
+        }
+
         let path = entry.path();
         
         // Skip the data directory itself
@@ -73,9 +692,20 @@ pub fn copy(extract_dir: PathBuf, verbose: bool) -> String {
             continue;
         }
 
-        // Get relative path from data/
-        let rel = path.strip_prefix(&data_dir).unwrap();
-        let dest = Path::new("/").join(rel);
+        let dest = normalize_path(&destination(path.strip_prefix(&data_dir).unwrap(), prefix_map));
+
+        // the cache can't have anything below a symlink, so only the system's symlinks matter here
+        if let Err(e) = opts.check_parent(&dest, &HashSet::new()) {
+            warn!("Refusing to install {}: {}, skipping...", dest.display(), e);
+            summary.failed += 1;
+            continue;
+        }
+
+        let is_file = entry.file_type().is_file() || (entry.file_type().is_symlink() && dereference);
+        if is_file && opts.keep_existing(&dest, std::fs::metadata(path).map_or(0, |metadata| metadata.mtime())) {
+            summary.skipped += 1;
+            continue;
+        }
 
         if verbose {
             info!("Copying {} to {}", path.display(), dest.display());
@@ -87,58 +717,234 @@ pub fn copy(extract_dir: PathBuf, verbose: bool) -> String {
             if let Some(parent) = dest.parent() {
                 let _ = std::fs::create_dir_all(parent);
             }
-            if entry.file_type().is_symlink() {
+            if entry.file_type().is_symlink() && dereference {
+                match resolve_in_package(&data_dir, path) {
+                    Some(target) if target.is_dir() => {
+                        warn!("Cannot dereference {}, it points to a directory, skipping...", dest.display());
+                        summary.skipped += 1;
+                        continue;
+                    },
+                    Some(target) => {
+                        // don't write through a symlink that's already at the destination
+                        if dest.symlink_metadata().is_ok_and(|m| m.file_type().is_symlink()) {
+                            let _ = std::fs::remove_file(&dest);
+                        }
+                        copy_file(&target, &dest)
+                    },
+                    None => {
+                        error!("Cannot dereference {}, it's a dangling symlink, skipping...", dest.display());
+                        summary.failed += 1;
+                        continue;
+                    },
+                }
+            } else if entry.file_type().is_symlink() {
                 let target = std::fs::read_link(path).unwrap();
                 if dest.exists() {
                     if dest.is_dir() {
                         warn!("Cannot create symlink {}, a directory with the same name exists.", dest.display());
+                        summary.skipped += 1;
                         continue;
                     }
                     std::fs::remove_file(&dest).unwrap();
                 }
                 std::os::unix::fs::symlink(&target, &dest)
             } else { // is_file()
-                std::fs::copy(&path, &dest).map(|_| ())
+                copy_file(path, &dest)
             }
         };
 
         if let Err(e) = result {
             warn!("Failed to copy {} to {}: {}, skipping...", 
                   path.display(), dest.display(), e);
+            summary.failed += 1;
             continue;
         }
 
-        copied_files.push(dest);
+        check_setuid(&dest, no_setuid);
+
+        copied_files.push(normalize_path(&dest));
     }
 
-    info!("Copied {} files/directories", copied_files.len());
+    summary.done = copied_files.len();
     copied_files.iter()
         .map(|s| s.display().to_string())
         .collect::<Vec<_>>()
         .join(",")
 }
 
-pub fn uninstall_by_pkg_name(pkg_name: String, conn: Connection, verbose: bool) {
-    let mut stmt = conn.prepare("SELECT * FROM debs WHERE package = ?").expect("Failed to prepare statement");
-    stmt.bind(1, pkg_name.as_str()).expect("Failed to bind id to prepared statement");
+/// Unpacks the data archive straight to the destination of every entry, without going through the cache.
+/// If the archive turns out to be broken halfway through, the error comes with the files installed until then
+pub fn stream(f: File, bar: ProgressBar, eta: &MixedEta, unit: ProgressUnit, limits: Limits, opts: &WriteOptions, summary: &mut Summary) -> Result<String, (std::io::Error, String)> {
+    let WriteOptions { verbose, prefix_map, no_setuid, .. } = *opts;
+    let mut installed_files: Vec<PathBuf> = vec![];
+    let mut links = HashSet::new();
+
+    let result = extract::for_each_data_entry(f, limits, |entry| {
+        // bytes are counted from the tar headers, the same sizes the total was added up from
+        match unit {
+            ProgressUnit::Files => {
+                bar.inc(1);
+                eta.inc(entry.size());
+            },
+            ProgressUnit::Bytes => bar.inc(entry.size()),
+        }
+
+        let Some(rel) = entry.path().ok().and_then(|path| extract::package_path(&path)) else {
+            return;
+        };
+        let dest = normalize_path(&destination(&rel, prefix_map));
+        let entry_type = entry.header().entry_type();
+
+        if let Err(e) = opts.check_parent(&dest, &links) {
+            warn!("Refusing to install {}: {}, skipping...", dest.display(), e);
+            summary.failed += 1;
+            return;
+        }
+
+        if entry_type.is_file() && opts.keep_existing(&dest, entry.header().mtime().unwrap_or(0) as i64) {
+            summary.skipped += 1;
+            return;
+        }
+
+        if verbose {
+            info!("Installing {}", dest.display());
+        }
+
+        let result = if entry_type == EntryType::Directory {
+            std::fs::create_dir_all(&dest)
+        } else {
+            if let Some(parent) = dest.parent() {
+                let _ = std::fs::create_dir_all(parent);
+            }
+
+            if entry_type == EntryType::Symlink {
+                let target = entry.link_name().ok().flatten().map(|target| target.into_owned()).unwrap_or_default();
+                if let Ok(metadata) = dest.symlink_metadata() {
+                    if metadata.is_dir() {
+                        warn!("Cannot create symlink {}, a directory with the same name exists.", dest.display());
+                        summary.skipped += 1;
+                        return;
+                    }
+                    let _ = std::fs::remove_file(&dest);
+                }
+                links.insert(dest.clone());
+                std::os::unix::fs::symlink(&target, &dest)
+            } else if entry_type == EntryType::Link {
+                // hard links point at another path inside the package
+                match entry.link_name().ok().flatten().and_then(|target| extract::package_path(&target)) {
+                    Some(target) => {
+                        // the same goes for what the link points at, and it has to be something this package installed
+                        let source = normalize_path(&destination(&target, prefix_map));
+                        if let Err(e) = opts.check_parent(&source, &links) {
+                            warn!("Refusing to link {} to {}: {}, skipping...", dest.display(), source.display(), e);
+                            summary.failed += 1;
+                            return;
+                        }
+                        if !installed_files.contains(&source) {
+                            warn!("Cannot create hard link {}, its target {} isn't installed by the package, skipping...", dest.display(), source.display());
+                            summary.skipped += 1;
+                            return;
+                        }
+
+                        let _ = std::fs::remove_file(&dest);
+                        std::fs::hard_link(&source, &dest)
+                    },
+                    None => {
+                        warn!("Cannot create hard link {}, its target is outside of the package, skipping...", dest.display());
+                        summary.skipped += 1;
+                        return;
+                    },
+                }
+            } else {
+                entry.unpack(&dest).map(|_| ())
+            }
+        };
+
+        if let Err(e) = result {
+            warn!("Failed to install {}: {}, skipping...", dest.display(), e);
+            summary.failed += 1;
+            return;
+        }
+
+        check_setuid(&dest, no_setuid);
+
+        installed_files.push(normalize_path(&dest));
+    });
+
+    bar.finish();
+
+    summary.done = installed_files.len();
+    let installed = installed_files.iter()
+        .map(|s| s.display().to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    match result {
+        Ok(()) => Ok(installed),
+        Err(e) => Err((e, installed)),
+    }
+}
+
+// fs::copy keeps the mode but not the modification time, which the cache still has from the archive
+fn copy_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::copy(from, to)?;
+
+    let modified = std::fs::metadata(from)?.modified()?;
+    File::options().write(true).open(to)?.set_modified(modified)
+}
+
+// warns about setuid/setgid files, or clears the bits with --no-setuid
+fn check_setuid(dest: &Path, no_setuid: bool) {
+    if let Ok(metadata) = dest.symlink_metadata()
+        && metadata.is_file()
+        && metadata.mode() & 0o6000 != 0
+    {
+        if no_setuid {
+            let mode = metadata.mode() & !0o6000;
+            match std::fs::set_permissions(dest, std::fs::Permissions::from_mode(mode)) {
+                Ok(()) => info!("Stripped setuid/setgid bits from {}", dest.display()),
+                Err(e) => warn!("Failed to strip setuid/setgid bits from {}: {}", dest.display(), e),
+            }
+        } else {
+            warn!("{} is installed setuid/setgid ({:o}), pass --no-setuid to strip it", dest.display(), metadata.mode() & 0o7777);
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct UninstallOptions {
+    pub verbose: bool,
+    pub quiet: bool,
+    pub strict: bool,
+    pub simulate: bool,
+    pub prompter: Prompter,
+}
+
+pub fn uninstall_by_pkg_name(pkg_name: String, conn: Connection, opts: UninstallOptions) {
+    let UninstallOptions { verbose, strict, simulate, prompter, .. } = opts;
+    let pkg_name = control::normalize_package_name(&pkg_name);
+
+    let mut stmt = conn.prepare("SELECT * FROM debs WHERE lower(package) = ?").expect("Failed to prepare statement");
+    stmt.bind(1, pkg_name.as_str()).expect("Failed to bind package name to prepared statement");
 
     let state = stmt.next().expect("Failed to get pkg by id");
 
-    if state == State::Row {
+    let removed = if state == State::Row {
         let mut map = HashMap::new();
         let col_names = stmt.column_names().unwrap();
+        let installed_col = col_names.iter().position(|col_name| col_name == "installed");
 
-        for i in 0..stmt.columns() {
-            let col_name = col_names[i].clone();
+        for (i, col_name) in col_names.into_iter().enumerate() {
 
-            if col_name == "package" { continue }
+            if col_name == "id" { continue }
 
             let val = match stmt.read::<Value>(i).expect("Failed to read value of column") {
                 Value::Binary(_) => "<binary>".to_string(),
                 Value::Float(f) => f.to_string(),
                 Value::Integer(i) => i.to_string(),
                 Value::String(s) => s,
-                Value::Null => "null".to_string(),
+                // missing fields are None rather than the string "null"
+                Value::Null => continue,
             };
             map.insert(col_name, val);
         }
@@ -150,32 +956,71 @@ pub fn uninstall_by_pkg_name(pkg_name: String, conn: Connection, verbose: bool)
                 std::process::exit(1);
             }
         };
-        let cwd = ControlWithData { ctrl, installed: map.get("installed").unwrap().to_string() };
+        // every row of the package gets deleted below, so the files of all of them are removed
+        // (e.g. an older version that was installed alongside and never uninstalled)
+        let mut installed: Vec<String> = installed_files(&map).split(',').filter(|s| !s.is_empty()).map(str::to_string).collect();
+        while stmt.next().expect("Failed to get row") == State::Row {
+            if let Some(col) = installed_col
+                && let Value::String(more) = stmt.read::<Value>(col).expect("Failed to read value of column")
+            {
+                for path in more.split(',').filter(|s| !s.is_empty()) {
+                    if !installed.iter().any(|seen| seen == path) {
+                        installed.push(path.to_string());
+                    }
+                }
+            }
+        }
+        let cwd = ControlWithData { ctrl, installed: installed.join(",") };
+
+        let deb_ids = installed_ids(&conn, &pkg_name, None);
+
+        if simulate {
+            print_uninstall_plan(&conn, &cwd, &deb_ids);
+            return;
+        }
 
-        uninstall_ctrl(cwd, verbose);
+        if !confirm_reverse_depends(&conn, &cwd.ctrl, prompter) {
+            return;
+        }
+
+        let (package, version) = (cwd.ctrl.package.clone(), cwd.ctrl.version.clone());
+        let deleted = uninstall_ctrl(cwd, verbose, strict);
+        backup::restore(&conn, &deb_ids);
+        Some((package, version, deleted))
+    } else if simulate {
+        info!("Package is not installed, nothing would be done");
+        return;
     } else {
         info!("Package is not installed, cleaning up...");
-    }
+        None
+    };
 
-    let mut delete_stmt = conn.prepare("DELETE FROM debs WHERE package = ?").expect("Failed to prepare DELETE statement");
+    conn.execute("BEGIN").expect("Failed to begin transaction");
+
+    let mut delete_stmt = conn.prepare("DELETE FROM debs WHERE lower(package) = ?").expect("Failed to prepare DELETE statement");
 
     delete_stmt.bind(1, pkg_name.as_str()).expect("Failed to bind package name to DELETE statement");
 
     delete_stmt.next().expect("Failed to run DELETE statement");
+
+    if let Some((package, version, deleted)) = removed {
+        history::record(&conn, history::UNINSTALL, &package, &version, deleted).expect("Failed to record history");
+    }
+    conn.execute("COMMIT").expect("Failed to commit transaction");
 }
 
-pub fn uninstall_by_id(id: usize, conn: Connection, verbose: bool) {
+pub fn uninstall_by_id(id: usize, conn: Connection, opts: UninstallOptions) {
+    let UninstallOptions { verbose, strict, simulate, prompter, .. } = opts;
     let mut stmt = conn.prepare("SELECT * FROM debs WHERE id = ?").expect("Failed to prepare statement");
     stmt.bind(1, id as i64).expect("Failed to bind id to prepared statement");
 
     let state = stmt.next().expect("Failed to get pkg by id");
 
-    if state == State::Row {
+    let removed = if state == State::Row {
         let mut map = HashMap::new();
         let col_names = stmt.column_names().unwrap();
 
-        for i in 0..stmt.columns() {
-            let col_name = col_names[i].clone();
+        for (i, col_name) in col_names.into_iter().enumerate() {
 
             if col_name == "id" { continue }
 
@@ -184,7 +1029,8 @@ pub fn uninstall_by_id(id: usize, conn: Connection, verbose: bool) {
                 Value::Float(f) => f.to_string(),
                 Value::Integer(i) => i.to_string(),
                 Value::String(s) => s,
-                Value::Null => "null".to_string(),
+                // missing fields are None rather than the string "null"
+                Value::Null => continue,
             };
             map.insert(col_name, val);
         }
@@ -196,37 +1042,63 @@ pub fn uninstall_by_id(id: usize, conn: Connection, verbose: bool) {
                 std::process::exit(1);
             }
         };
-        let cwd = ControlWithData { ctrl, installed: map.get("installed").unwrap().to_string() };
-        uninstall_ctrl(cwd, verbose);
-    }
+        let cwd = ControlWithData { ctrl, installed: installed_files(&map) };
+        if simulate {
+            print_uninstall_plan(&conn, &cwd, &[id as i64]);
+            return;
+        }
+
+        if !confirm_reverse_depends(&conn, &cwd.ctrl, prompter) {
+            return;
+        }
+
+        let (package, version) = (cwd.ctrl.package.clone(), cwd.ctrl.version.clone());
+        let deleted = uninstall_ctrl(cwd, verbose, strict);
+        backup::restore(&conn, &[id as i64]);
+        Some((package, version, deleted))
+    } else if simulate {
+        info!("No package with id {}, nothing would be done", id);
+        return;
+    } else {
+        None
+    };
+
+    conn.execute("BEGIN").expect("Failed to begin transaction");
 
     let mut delete_stmt = conn.prepare("DELETE FROM debs WHERE id = ?").expect("Failed to prepare DELETE statement");
 
     delete_stmt.bind(1, id as i64).expect("Failed to bind id to DELETE statement");
 
     delete_stmt.next().expect("Failed to run DELETE statement");
+
+    if let Some((package, version, deleted)) = removed {
+        history::record(&conn, history::UNINSTALL, &package, &version, deleted).expect("Failed to record history");
+    }
+    conn.execute("COMMIT").expect("Failed to commit transaction");
 }
 
-pub fn uninstall(deb: ClioPath, dirs: ProjectDirs, conn: Connection, verbose: bool) {
+pub fn uninstall(deb: ClioPath, cache_dir: &Path, conn: Connection, opts: UninstallOptions) {
+    let UninstallOptions { verbose, quiet, strict, simulate, prompter } = opts;
     if !deb.exists() {
         error!("Failed to install .deb file because the .deb file you specified does not exist.");
         std::process::exit(-1);
     }
 
-    if deb.extension().is_none_or(|ext| ext != "deb") {
+    if !extract::is_deb(&deb) {
         error!("Failed to uninstall .deb file because the file you specified isn't one.");
         std::process::exit(-1);
     }
 
-    let f = File::open(deb.to_path_buf()).unwrap();
+    extract::check_members(&deb);
 
-    let cache_dir = dirs.cache_dir();
     let extract_dir = cache_dir.join("extracted");
 
     let _ = std::fs::remove_dir_all(&extract_dir);
 
-    let opt_ctrl = extract::extract_control(f);
+    let spinner = extract::spinner("Reading package metadata...", quiet);
+    let opt_ctrl = extract::extract_control(extract::open(&deb));
     if opt_ctrl.is_none() {
+        spinner.finish_and_clear();
         error!("Failed to get control file from .deb, make sure the .deb is valid");
         std::process::exit(-1);
     }
@@ -235,30 +1107,57 @@ pub fn uninstall(deb: ClioPath, dirs: ProjectDirs, conn: Connection, verbose: bo
     let ctrl = match control::parse_control(ctrl_str) {
         Ok(ctrl) => ctrl,
         Err(e) => {
+            spinner.finish_and_clear();
             error!("Failed to parse control file: {}", e);
             std::process::exit(1);
         }
     };
+    spinner.finish_and_clear();
     let installed_ctrl = ControlWithData::from_db(&conn, &ctrl.package, &ctrl.version);
 
     match installed_ctrl {
+        Ok(installed_ctrl) if installed_ctrl.ctrl == ctrl && simulate => {
+            print_uninstall_plan(&conn, &installed_ctrl, &installed_ids(&conn, &ctrl.package, Some(&ctrl.version)));
+            return;
+        },
+
         Ok(installed_ctrl) if installed_ctrl.ctrl == ctrl => {
-            uninstall_ctrl(installed_ctrl, verbose);
+            if !confirm_reverse_depends(&conn, &ctrl, prompter) {
+                return;
+            }
+
+            let deb_ids = installed_ids(&conn, &ctrl.package, Some(&ctrl.version));
+            let deleted = uninstall_ctrl(installed_ctrl, verbose, strict);
+            backup::restore(&conn, &deb_ids);
+
+            conn.execute("BEGIN").expect("Failed to begin transaction");
+
             let query = "DELETE FROM debs WHERE package = ? AND version = ?";
 
-            let stmt = conn.prepare(&query);
+            let stmt = conn.prepare(query);
             let mut stmt = stmt.expect("Failed to prepare delete statement.");
 
             stmt.bind(1, ctrl.package.as_str()).expect("Failed to bind package name");
             stmt.bind(2, ctrl.version.as_str()).expect("Failed to bind version");
             stmt.next().expect("Failed to execute deletion");
+
+            history::record(&conn, history::UNINSTALL, &ctrl.package, &ctrl.version, deleted).expect("Failed to record history");
+            conn.execute("COMMIT").expect("Failed to commit transaction");
+        },
+
+        Err(LookupError::NotInstalled) => {
+            error!("{} is not installed", ctrl.package);
+            std::process::exit(-1);
+        },
+
+        Err(LookupError::VersionMismatch { installed }) => {
+            error!("{} {} is not installed ({} is), uninstall it by name instead", ctrl.package, ctrl.version, installed);
+            std::process::exit(-1);
         },
 
         Err(err) => {
-            if let Some(msg) = err.message {
-                error!("An error occured while trying to delete the .deb file from the db: {}", msg);
-                std::process::exit(-1);
-            }
+            error!("An error occured while trying to delete the .deb file from the db: {}", err);
+            std::process::exit(-1);
         },
 
         _ => {}
@@ -267,53 +1166,155 @@ pub fn uninstall(deb: ClioPath, dirs: ProjectDirs, conn: Connection, verbose: bo
     info!("Uninstalled .deb package.");
 }
 
-pub fn uninstall_ctrl(ctrl: ControlWithData, verbose: bool) {
-    let installed_paths: Vec<PathBuf> = ctrl.installed
+// the database rows of an installed package, `None` matches every version since reinstalling adds another row
+fn installed_ids(conn: &Connection, package: &str, version: Option<&str>) -> Vec<i64> {
+    let mut stmt = conn.prepare("SELECT id FROM debs WHERE lower(package) = ? AND (? IS NULL OR version = ?)").expect("Failed to prepare statement");
+    stmt.bind(1, control::normalize_package_name(package).as_str()).expect("Failed to bind package name");
+    match version {
+        Some(version) => {
+            stmt.bind(2, version).expect("Failed to bind version");
+            stmt.bind(3, version).expect("Failed to bind version");
+        },
+        None => {
+            stmt.bind(2, ()).expect("Failed to bind version");
+            stmt.bind(3, ()).expect("Failed to bind version");
+        },
+    }
+
+    let mut ids = vec![];
+    while stmt.next().expect("Failed to get row") == State::Row {
+        ids.push(stmt.read::<i64>(0).expect("Failed to read id"));
+    }
+
+    ids
+}
+
+fn last_insert_id(conn: &Connection) -> Result<i64, sqlite3::Error> {
+    let mut stmt = conn.prepare("SELECT last_insert_rowid()")?;
+    stmt.next()?;
+    stmt.read::<i64>(0)
+}
+
+// `installed` is NULL for rows that never got a file list (e.g. a failed install), treat those as empty
+fn installed_files(row: &HashMap<String, String>) -> String {
+    row.get("installed").cloned().unwrap_or_else(|| {
+        warn!("{} has no recorded files, only removing it from the database", row.get("package").map_or("package", |s| s.as_str()));
+        String::new()
+    })
+}
+
+// warns about the installed packages that depend on `ctrl` and asks whether to uninstall it anyway
+fn confirm_reverse_depends(conn: &Connection, ctrl: &control::Control, prompter: Prompter) -> bool {
+    let needed_by = match deps::reverse_depends(conn, ctrl) {
+        Ok(needed_by) => needed_by,
+        Err(e) => {
+            warn!("Failed to check reverse dependencies: {}", e);
+            return true;
+        }
+    };
+
+    if needed_by.is_empty() {
+        return true;
+    }
+
+    warn!("{} is needed by {}", ctrl.package, needed_by.join(", "));
+    if prompter.confirm(&format!("Uninstall {} anyway?", ctrl.package), true) {
+        true
+    } else {
+        info!("Nothing was uninstalled");
+        false
+    }
+}
+
+// the files and symlinks of an installed package that are still on disk, directories are never removed
+fn removable_paths(ctrl: &ControlWithData) -> Vec<PathBuf> {
+    ctrl.installed
         .split(',')
         .filter(|s| !s.is_empty())
-        .map(|s| PathBuf::from(s.trim()))
-        .collect();
+        .map(|s| normalize_path(Path::new(s.trim())))
+        .filter(|path| std::fs::symlink_metadata(path)
+            .is_ok_and(|metadata| metadata.file_type().is_file() || metadata.file_type().is_symlink()))
+        .collect()
+}
+
+// the recorded files and symlinks that aren't on disk anymore, e.g. because they were deleted by hand
+fn missing_paths(ctrl: &ControlWithData) -> Vec<PathBuf> {
+    ctrl.installed
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(|s| normalize_path(Path::new(s.trim())))
+        .filter(|path| std::fs::symlink_metadata(path).is_err())
+        .collect()
+}
 
-    let mut deleted = 0;
+// everything an uninstall would do, without touching the filesystem or the database
+fn print_uninstall_plan(conn: &Connection, ctrl: &ControlWithData, deb_ids: &[i64]) {
+    info!("Would uninstall {} {}:", ctrl.ctrl.package, ctrl.ctrl.version);
+    for path in removable_paths(ctrl) {
+        info!("  remove {}", path.display());
+    }
+    for (path, backup) in backup::backups(conn, deb_ids).unwrap_or_default() {
+        info!("  restore {} from {}", path.display(), backup.display());
+    }
+    info!("  delete {} {} from the database and record it in the history", ctrl.ctrl.package, ctrl.ctrl.version);
+    info!("  no maintainer scripts would be run");
+}
 
-    for path in installed_paths {
-        if let Ok(metadata) = std::fs::symlink_metadata(&path) {
-            if metadata.file_type().is_file() || metadata.file_type().is_symlink() {
-                if verbose {
-                    info!("Deleting {}...", path.to_str().unwrap());
-                }
+/// Removes the files of an installed package. Files that are already gone don't fail the uninstall,
+/// they're only counted, or each warned about with `strict`
+pub fn uninstall_ctrl(ctrl: ControlWithData, verbose: bool, strict: bool) -> usize {
+    let mut summary = Summary::new("files");
 
-                if let Err(e) = std::fs::remove_file(&path) {
-                    warn!("Failed to remove file/symlink {}: {}", path.display(), e);
-                } else {
-                    deleted += 1;
-                }
-            }
+    let missing = missing_paths(&ctrl);
+    if strict {
+        for path in &missing {
+            warn!("{} was installed by {} but is already gone", path.display(), ctrl.ctrl.package);
         }
     }
-    info!("Deleted {deleted} files");
+    if !missing.is_empty() {
+        info!("{} files already absent", missing.len());
+    }
+    summary.skipped = missing.len();
+
+    for path in removable_paths(&ctrl) {
+        if verbose {
+            info!("Deleting {}...", path.to_str().unwrap());
+        }
+
+        if let Err(e) = std::fs::remove_file(&path) {
+            warn!("Failed to remove file/symlink {}: {}", path.display(), e);
+            summary.failed += 1;
+        } else {
+            debug!("{}: removed {}", ctrl.ctrl.package, path.display());
+            summary.done += 1;
+        }
+    }
+    summary.print(&format!("Uninstalled {} {}", ctrl.ctrl.package, ctrl.ctrl.version), false);
+
+    summary.done
 }
 
-pub fn is_installed(deb: ClioPath, dirs: ProjectDirs, conn: Connection) {
+pub fn is_installed(deb: ClioPath, cache_dir: &Path, conn: Connection, quiet: bool, explain: bool, file: Option<PathBuf>, limits: Limits) {
     if !deb.exists() {
         error!("Failed to install .deb file because the .deb file you specified does not exist.");
         std::process::exit(-1);
     }
 
-    if deb.extension().is_none_or(|ext| ext != "deb") {
+    if !extract::is_deb(&deb) {
         error!("Failed to uninstall .deb file because the file you specified isn't one.");
         std::process::exit(-1);
     }
 
-    let f = File::open(deb.to_path_buf()).unwrap();
+    extract::check_members(&deb);
 
-    let cache_dir = dirs.cache_dir();
     let extract_dir = cache_dir.join("extracted");
 
     let _ = std::fs::remove_dir_all(&extract_dir);
 
-    let opt_ctrl = extract::extract_control(f);
+    let spinner = extract::spinner("Reading package metadata...", quiet);
+    let opt_ctrl = extract::extract_control(extract::open(&deb));
     if opt_ctrl.is_none() {
+        spinner.finish_and_clear();
         error!("Failed to get control file from .deb, make sure the .deb is valid");
         std::process::exit(-1);
     }
@@ -322,10 +1323,18 @@ pub fn is_installed(deb: ClioPath, dirs: ProjectDirs, conn: Connection) {
     let ctrl = match control::parse_control(ctrl_str) {
         Ok(ctrl) => ctrl,
         Err(e) => {
+            spinner.finish_and_clear();
             error!("Failed to parse control file: {}", e);
             std::process::exit(1);
         }
     };
+    spinner.finish_and_clear();
+
+    if let Some(file) = file {
+        check_file(&conn, &deb, &ctrl, &file, limits);
+        return;
+    }
+
     let installed_ctrl = ControlWithData::from_db(&conn, &ctrl.package, &ctrl.version);
 
     match installed_ctrl {
@@ -333,27 +1342,362 @@ pub fn is_installed(deb: ClioPath, dirs: ProjectDirs, conn: Connection) {
             info!("The specified package {} installed.", "IS".bold().italic());
         },
 
-        _ => {
+        lookup => {
             info!("The specified package is {} installed.", "NOT".bold().red().italic());
+
+            if explain {
+                explain_not_installed(&ctrl, lookup);
+            }
+        }
+    }
+}
+
+// whether the package has `file` and what installing it would do to the file on disk, without installing anything
+fn check_file(conn: &Connection, deb: &Path, ctrl: &control::Control, file: &Path, limits: Limits) {
+    let file = normalize_path(&Path::new("/").join(file));
+
+    let paths = extract::data_paths(extract::open(deb), limits)
+        .unwrap_or_else(|e| extract::extract_failed(deb, e));
+    if !paths.iter().any(|(path, _)| normalize_path(&Path::new("/").join(path)) == file) {
+        info!("{} {} doesn't contain {}", ctrl.package, ctrl.version, file.display());
+        return;
+    }
+    info!("{} {} contains {}", ctrl.package, ctrl.version, file.display());
+
+    let metadata = match file.symlink_metadata() {
+        Ok(metadata) => metadata,
+        Err(_) => {
+            info!("{} doesn't exist, installing would create it", file.display());
+            return;
         }
+    };
+    if metadata.is_dir() {
+        info!("{} is an existing directory, installing would only add to it", file.display());
+        return;
+    }
+
+    match files::owning_package(conn, &file.display().to_string()) {
+        Ok(Some(owner)) if control::normalize_package_name(&owner) == control::normalize_package_name(&ctrl.package) => {
+            info!("{} belongs to {}, installing would replace it", file.display(), owner);
+        },
+        Ok(Some(owner)) => warn!("Installing would overwrite {}, which belongs to {}", file.display(), owner),
+        Ok(None) => warn!("Installing would overwrite {}, which exists but wasn't installed by debby (see --force-overwrite)", file.display()),
+        Err(e) => warn!("Failed to look up the owner of {}: {}", file.display(), e),
+    }
+}
+
+// how the closest thing to `ctrl` in the database differs from it
+fn explain_not_installed(ctrl: &control::Control, lookup: Result<ControlWithData, LookupError>) {
+    let installed = match lookup {
+        Ok(installed) => installed.ctrl,
+        Err(LookupError::NotInstalled) => {
+            info!("No row for package {} in the database", ctrl.package);
+            return;
+        },
+        Err(LookupError::VersionMismatch { installed }) => {
+            info!("Package found but version differs (installed {}, checked {})", installed, ctrl.version);
+            return;
+        },
+        Err(e) => {
+            warn!("Failed to look up {}: {}", ctrl.package, e);
+            return;
+        },
+    };
+
+    let differing = ctrl.values().into_iter()
+        .zip(installed.values())
+        .filter(|((_, checked), (_, installed))| checked != installed)
+        .map(|((field, _), _)| control::control_field_name(field))
+        .collect::<Vec<_>>();
+    info!("Package found at the same version but its control fields differ: {}", differing.join(", "));
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum AllFormat {
+    /// A table per package
+    Table,
+    /// A header row, then one tab separated line per package, for cut/awk
+    Tsv,
+}
+
+/// What `all` shows without `--columns`
+pub const DEFAULT_COLUMNS: &[&str] = &["package", "version", "architecture", "description_short"];
+
+// stored next to the control fields, split from the description
+const DESCRIPTION_COLUMNS: &[&str] = &["description_short", "description_long"];
+
+/// Checks an `all --columns` entry against the columns of the debs table, `Installed-Size` works too
+pub fn parse_column(s: &str) -> Result<String, String> {
+    let column = control::normalize_field_name(s);
+
+    if column == "id" || column == "installed" || DESCRIPTION_COLUMNS.contains(&column.as_str()) || control::Control::fields().contains(&column) {
+        Ok(column)
+    } else {
+        Err(format!("unknown column, expected id, installed, {} or one of {}", DESCRIPTION_COLUMNS.join(", "), control::Control::fields().join(", ")))
     }
 }
 
-pub fn all(conn: Connection) {
-    let mut stmt = conn.prepare("SELECT * FROM debs").expect("Failed to prepare statement");
+pub fn all(conn: Connection, columns: &[String], full: bool, format: AllFormat, out: &mut Output) {
+    // the installed file list can be huge, so it's only shown when asked for
+    let columns = if !columns.is_empty() {
+        columns.join(", ")
+    } else if full {
+        "*".to_string()
+    } else {
+        DEFAULT_COLUMNS.join(", ")
+    };
+
+    // every column went through parse_column, so they're safe to put in the query
+    let mut stmt = conn.prepare(format!("SELECT {} FROM debs", columns)).expect("Failed to prepare statement");
+
+    if let AllFormat::Tsv = format {
+        writeln!(out, "{}", stmt.column_names().unwrap().join("\t")).expect("Failed to print all installed packages");
+
+        while stmt.next().expect("Failed to get row") == State::Row {
+            let row = (0..stmt.columns())
+                .map(|i| tsv_escape(&stmt.read::<String>(i).unwrap_or_default()))
+                .collect::<Vec<_>>();
+            writeln!(out, "{}", row.join("\t")).expect("Failed to print all installed packages");
+        }
+
+        return;
+    }
 
+    let mut any = false;
     while stmt.next().expect("Failed to get row") == State::Row {
         let mut table: Vec<Vec<String>> = vec![];
 
         for i in 0..stmt.columns() {
             let col = stmt.column_names().unwrap()[i].clone();
             if let Ok(val) = stmt.read::<String>(i) {
-                table.push(vec![col, view::truncate(val.as_str(), 50)]);
+                let val = if full { view::wrap(&val, 80) } else { view::truncate(val.as_str(), 50) };
+                table.push(vec![col, val]);
             }
         }
 
-        cli_table::print_stdout(table).expect("Failed to print all installed packages");
+        view::print_table(out, table.table());
+
+        writeln!(out).expect("Failed to print all installed packages");
+        any = true;
+    }
+
+    if any && !full {
+        info!("Pass --full or --columns to see more");
+    }
+}
+
+/// How many packages are installed per architecture, most common first, e.g. to spot foreign-arch packages
+pub fn architecture_report(conn: Connection, format: AllFormat, out: &mut Output) {
+    let mut stmt = conn.prepare("SELECT architecture, COUNT(*) FROM debs GROUP BY architecture ORDER BY COUNT(*) DESC, architecture")
+        .expect("Failed to prepare statement");
+
+    let mut counts = vec![];
+    while stmt.next().expect("Failed to get row") == State::Row {
+        counts.push((stmt.read::<String>(0).unwrap_or_default(), stmt.read::<i64>(1).expect("Failed to read count")));
+    }
+
+    if let AllFormat::Tsv = format {
+        writeln!(out, "architecture\tpackages").expect("Failed to print architecture report");
+        for (architecture, count) in &counts {
+            writeln!(out, "{}\t{}", tsv_escape(architecture), count).expect("Failed to print architecture report");
+        }
+        return;
+    }
+
+    if counts.is_empty() {
+        info!("No packages installed yet.");
+        return;
+    }
+
+    let table: Vec<Vec<String>> = counts.into_iter()
+        .map(|(architecture, count)| vec![architecture, count.to_string()])
+        .collect();
+    view::print_table(out, table.table().title(vec!["architecture".cell().bold(true), "packages".cell().bold(true)]));
+}
+
+// tabs and newlines would break up the row, so they're escaped like in postgres' text format
+fn tsv_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use crate::extract::tests::{ar_member, deb, gz, tar, MTIME, NO_LIMITS};
+
+    use super::*;
+
+    // an in-memory database with `package` installed, owning the files in `installed`
+    fn db_with(package: &str, installed: &str) -> Connection {
+        let conn = sqlite3::open(":memory:").expect("Failed to open database");
+        crate::db::migrate(&conn).expect("Failed to migrate database");
+
+        let control = format!("Package: {}\nVersion: 1.0\nArchitecture: all\nMaintainer: Jane Doe <jane@example.org>\n", package);
+        let (cols, vals) = control::parse_control(control).expect("Failed to parse control").populate_sql();
+        conn.execute(format!("INSERT INTO debs ({}, installed) VALUES ({}, '{}')", cols, vals, installed.replace('\'', "''")))
+            .expect("Failed to insert package");
+
+        conn
+    }
+
+    // a package whose files were never recorded, like after a failed install
+    fn db_without_files() -> Connection {
+        let conn = db_with("hello", "");
+        conn.execute("UPDATE debs SET installed = NULL").expect("Failed to clear installed files");
+        conn
+    }
+
+    #[test]
+    fn normalize_path_drops_trailing_slashes() {
+        assert_eq!(normalize_path(Path::new("/usr/bin/")), Path::new("/usr/bin"));
+        assert_eq!(normalize_path(Path::new("/usr/bin//")), Path::new("/usr/bin"));
+        assert_eq!(normalize_path(Path::new("/")), Path::new("/"));
+    }
+
+    #[test]
+    fn normalize_path_drops_current_dirs() {
+        assert_eq!(normalize_path(Path::new("/usr/./bin")), Path::new("/usr/bin"));
+        assert_eq!(normalize_path(Path::new("./usr/bin")), Path::new("usr/bin"));
+        assert_eq!(normalize_path(Path::new("/usr/bin/.")), Path::new("/usr/bin"));
+        assert_eq!(normalize_path(Path::new("/usr/lib/../bin")), Path::new("/usr/bin"));
+    }
+
+    #[test]
+    fn copied_files_keep_their_modification_time() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let (from, to) = (dir.path().join("from"), dir.path().join("to"));
+        std::fs::write(&from, "hello").expect("Failed to write file");
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(MTIME);
+        File::options().write(true).open(&from).and_then(|f| f.set_modified(mtime)).expect("Failed to set mtime");
+
+        copy_file(&from, &to).expect("Failed to copy file");
+        assert_eq!(std::fs::metadata(&to).and_then(|m| m.modified()).expect("Failed to read mtime"), mtime);
+    }
+
+    // streams a package with the `data` tar into `dir`, which stands in for /opt
+    fn stream_into(dir: &Path, data: &[u8]) {
+        let f = deb(&[ar_member("debian-binary", b"2.0\n"), ar_member("data.tar.gz", &gz(data))]);
+        let prefix_map = [PrefixMap { from: PathBuf::from("/opt"), to: dir.to_path_buf() }];
+
+        let opts = WriteOptions { verbose: false, prefix_map: &prefix_map, no_setuid: false, no_overwrite_newer: false, deny_paths: &[], refuse_dangerous: false };
+        stream(f, ProgressBar::hidden(), &MixedEta::new(0), ProgressUnit::Files, NO_LIMITS, &opts, &mut Summary::new("files")).expect("Failed to install");
+    }
+
+    #[test]
+    fn streamed_files_keep_the_archive_modification_time() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        stream_into(dir.path(), &tar(&[("./opt/hello/file", b"hello")]));
+
+        let installed = dir.path().join("hello/file");
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(MTIME);
+        assert_eq!(std::fs::metadata(&installed).and_then(|m| m.modified()).expect("Failed to read mtime"), mtime);
+    }
+
+    #[test]
+    fn uninstalling_restores_overwritten_files() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let path = dir.path().join("conf");
+        std::fs::write(&path, "original").expect("Failed to write file");
+
+        // what installing with --force-overwrite does
+        backup::backup(std::slice::from_ref(&path)).expect("Failed to back up");
+        std::fs::write(&path, "packaged").expect("Failed to overwrite file");
+        let conn = db_with("hello", &path.display().to_string());
+        backup::record(&conn, 1, &path).expect("Failed to record backup");
+
+        uninstall_by_pkg_name("hello".to_string(), conn, uninstall_options());
+
+        assert_eq!(std::fs::read_to_string(&path).expect("Failed to read restored file"), "original");
+        assert!(!backup::backup_path(&path).exists());
+    }
+
+    #[test]
+    fn uninstalling_by_name_removes_the_files_of_every_row() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let (first, second) = (dir.path().join("first"), dir.path().join("second"));
+        std::fs::write(&first, "1.0").expect("Failed to write file");
+        std::fs::write(&second, "2.0").expect("Failed to write file");
+
+        // a second version left behind next to the first one
+        let conn = db_with("hello", &first.display().to_string());
+        let control = "Package: hello\nVersion: 2.0\nArchitecture: all\nMaintainer: Jane Doe <jane@example.org>\n".to_string();
+        let (cols, vals) = control::parse_control(control).expect("Failed to parse control").populate_sql();
+        conn.execute(format!("INSERT INTO debs ({}, installed) VALUES ({}, '{}')", cols, vals, second.display()))
+            .expect("Failed to insert package");
+
+        uninstall_by_pkg_name("hello".to_string(), conn, uninstall_options());
+
+        assert!(!first.exists());
+        assert!(!second.exists());
+    }
+
+    fn uninstall_options() -> UninstallOptions {
+        UninstallOptions { verbose: false, quiet: true, strict: false, simulate: false, prompter: Prompter::new(true, false) }
+    }
+
+    #[test]
+    fn null_installed_is_no_files() {
+        let conn = db_without_files();
+
+        let cwd = ControlWithData::from_db(&conn, "hello", "1.0").expect("Failed to look up hello");
+        assert_eq!(cwd.installed, "");
+        assert!(removable_paths(&cwd).is_empty());
+        assert_eq!(installed_files(&HashMap::from([("package".to_string(), "hello".to_string())])), "");
+    }
+
+    #[test]
+    fn uninstalling_a_package_without_files_does_not_panic() {
+        uninstall_by_pkg_name("hello".to_string(), db_without_files(), uninstall_options());
+        uninstall_by_id(1, db_without_files(), uninstall_options());
+    }
+
+    #[test]
+    fn relocated_symlinks_keep_their_targets() {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, target) in [("./opt/hello/lib.so", "/usr/lib/libfoo.so"), ("./opt/hello/doc", "../share/doc")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(EntryType::Symlink);
+            header.set_size(0);
+            header.set_mode(0o777);
+            header.set_mtime(MTIME);
+            builder.append_link(&mut header, path, target).expect("Failed to add symlink to tar");
+        }
+        let data = builder.into_inner().expect("Failed to finish tar");
+
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        stream_into(dir.path(), &data);
+
+        // only where the links are goes through --prefix-map, what they point at doesn't
+        assert_eq!(std::fs::read_link(dir.path().join("hello/lib.so")).expect("Failed to read symlink"), Path::new("/usr/lib/libfoo.so"));
+        assert_eq!(std::fs::read_link(dir.path().join("hello/doc")).expect("Failed to read symlink"), Path::new("../share/doc"));
+    }
+
+    #[test]
+    fn hard_links_only_point_at_files_of_the_package() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        std::fs::create_dir(dir.path().join("hello")).expect("Failed to create dir");
+        std::fs::write(dir.path().join("hello/secret"), "not packaged").expect("Failed to write file");
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_mode(0o644);
+        header.set_mtime(MTIME);
+        builder.append_data(&mut header, "./opt/hello/file", &b"hello"[..]).expect("Failed to add file to tar");
+        for (path, target) in [("./opt/hello/link", "./opt/hello/file"), ("./opt/hello/stolen", "./opt/hello/secret")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(EntryType::Link);
+            header.set_size(0);
+            header.set_mode(0o644);
+            header.set_mtime(MTIME);
+            builder.append_link(&mut header, path, target).expect("Failed to add hard link to tar");
+        }
+        let data = builder.into_inner().expect("Failed to finish tar");
+
+        stream_into(dir.path(), &data);
 
-        println!();
+        assert_eq!(std::fs::read_to_string(dir.path().join("hello/link")).expect("Failed to read hard link"), "hello");
+        assert!(!dir.path().join("hello/stolen").exists());
     }
 }
@@ -0,0 +1,19 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigint(_: libc::c_int) {
+    // only async-signal-safe things are allowed in here, storing to an atomic is one of them
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Makes Ctrl-C set a flag instead of killing debby, from then on long running loops have to check `interrupted`
+pub fn catch() {
+    let handler: extern "C" fn(libc::c_int) = on_sigint;
+    unsafe { libc::signal(libc::SIGINT, handler as libc::sighandler_t) };
+}
+
+/// Whether Ctrl-C was pressed since `catch`
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
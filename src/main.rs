@@ -2,17 +2,32 @@ pub mod install;
 pub mod view;
 pub mod control;
 pub mod extract;
+pub mod deps;
+pub mod history;
+pub mod db;
+pub mod prompt;
+pub mod verify;
+pub mod files;
+pub mod output;
+pub mod backup;
+pub mod summary;
+pub mod unpack;
+pub mod pin;
+pub mod doc;
+pub mod audit;
+pub mod interrupt;
 
-use std::{fs, str::FromStr};
+use std::{fs, path::{Path, PathBuf}, str::FromStr, sync::OnceLock, time::{SystemTime, UNIX_EPOCH}};
 
+use chrono::NaiveDateTime;
 use clap::{Parser, Subcommand};
-use clio::ClioPath;
+use clio::{ClioPath, Output, OutputPath};
 use directories::ProjectDirs;
-use log::{error, trace, Level};
+use log::{error, trace, warn, Level};
 use sqlite3::Connection;
 use std::io::Write as _;
 
-use crate::control::Control;
+use crate::{db::IntegrityError, deps::AssumeInstalled, extract::{Limits, ProgressMode, ProgressUnit}, install::{AllFormat, InstallOptions, PrefixMap, UninstallOptions}, output::{ColorMode, Format}, pin::Pins, prompt::Prompter, unpack::UnpackOptions, view::{ViewFormat, ViewOptions}};
 
 #[derive(Parser)]
 #[command(
@@ -24,6 +39,54 @@ struct Cli {
     #[arg(short, long, help = "Enable verbose logging (alias: v)")]
     verbose: bool,
 
+    #[arg(short, long, help = "Don't show progress bars or spinners")]
+    quiet: bool,
+
+    #[arg(long, value_enum, default_value_t = ProgressMode::Auto, help = "When to show progress bars and spinners")]
+    progress: ProgressMode,
+
+    #[arg(long, value_enum, default_value_t = ColorMode::Auto, help = "When to color the install/uninstall summary and file lists")]
+    color: ColorMode,
+
+    #[arg(long, value_enum, default_value_t = ProgressUnit::Files, help = "What progress bars count")]
+    progress_unit: ProgressUnit,
+
+    #[arg(long, value_name = "TEMPLATE", help = "indicatif template to draw progress bars with, e.g. \"{wide_bar} {pos}/{len}\"")]
+    progress_template: Option<String>,
+
+    #[arg(long, value_name = "SIZE", value_parser = extract::parse_size, default_value = "10G", help = "Refuse packages with an archive that unpacks to more than SIZE (e.g. 500M, 20G)")]
+    max_uncompressed_size: u64,
+
+    #[arg(long, value_name = "N", default_value_t = 1_000_000, help = "Refuse packages with an archive that has more than N files and directories")]
+    max_entries: u64,
+
+    #[arg(long, value_name = "PATH", help = "Also append timestamped logs to a file")]
+    log_file: Option<PathBuf>,
+
+    #[arg(long, help = "Print what install/uninstall would do without changing anything")]
+    simulate: bool,
+
+    #[arg(short, long, help = "Answer yes to every question instead of asking")]
+    yes: bool,
+
+    #[arg(long, conflicts_with = "yes", help = "Answer no to every question instead of asking")]
+    no: bool,
+
+    #[arg(long, help = "Never escalate through sudo, run with the current privileges instead")]
+    no_sudo: bool,
+
+    #[arg(long, help = "Log every SQL statement debby runs, with its parameters (may include file paths)")]
+    debug_sql: bool,
+
+    #[arg(long, value_name = "PATH", help = "Database to use instead of the default one in the data directory")]
+    db: Option<PathBuf>,
+
+    #[arg(long, value_name = "DIR", help = "Directory to unpack packages in instead of the default cache directory")]
+    cache: Option<PathBuf>,
+
+    #[arg(short, long, value_parser, default_value = "-", help = "Write command output (tables, file trees) to a file instead of stdout")]
+    output: OutputPath,
+
     #[command(subcommand)]
     cmd: Commands
 }
@@ -41,7 +104,7 @@ impl FromStr for UninstallInput {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if let Ok(id) = s.parse::<usize>() {
             Ok(UninstallInput::Id(id))
-        } else if s.ends_with(".deb") {
+        } else if extract::is_deb(Path::new(s)) {
             match ClioPath::new(s) {
                 Ok(path) => Ok(UninstallInput::Path(path)),
                 Err(e) => Err(e.to_string()),
@@ -56,31 +119,225 @@ impl FromStr for UninstallInput {
 enum Commands {
     #[command(alias = "i", about = "Install a package (alias: i)")]
     Install {
-        deb: ClioPath
+        deb: ClioPath,
+
+        #[arg(long = "assume-installed", value_name = "PKG[=VERSION]", help = "Treat a package as installed when checking dependencies (repeatable)")]
+        assume_installed: Vec<AssumeInstalled>,
+
+        #[arg(long = "prefix-map", value_name = "FROM=TO", help = "Install files under FROM into TO instead (repeatable, applied in order)")]
+        prefix_map: Vec<PrefixMap>,
+
+        #[arg(long, help = "Refuse to install packages without a valid _gpgorigin signature")]
+        require_signature: bool,
+
+        #[arg(long, value_name = "PATH", help = "Keyring to verify package signatures against (default: gpgv's trustedkeys)")]
+        keyring: Option<PathBuf>,
+
+        #[arg(long, help = "Install the files symlinks point to instead of the symlinks themselves")]
+        dereference: bool,
+
+        #[arg(long, help = "Clear the setuid and setgid bits of installed files")]
+        no_setuid: bool,
+
+        #[arg(long, help = "Refuse to install packages that write to sensitive paths like /etc/sudoers or /bin/sh")]
+        safe: bool,
+
+        #[arg(long, help = "Install even if the package writes to sensitive paths with --safe")]
+        allow_dangerous: bool,
+
+        #[arg(long = "deny-path", value_name = "PATH", help = "Also treat PATH (and everything below it) as sensitive (repeatable)")]
+        deny_paths: Vec<PathBuf>,
+
+        #[arg(long, help = "Keep existing files that were modified more recently than the ones in the package")]
+        no_overwrite_newer: bool,
+
+        #[arg(long, help = "Overwrite files that weren't installed by debby, after backing them up to <file>.debby-orig")]
+        force_overwrite: bool,
+
+        #[arg(long, help = "Only put the files in place without recording the package, so debby can't uninstall it")]
+        no_register: bool,
+
+        #[arg(long, help = "Warn if Installed-Size doesn't match the size of the files in the package")]
+        verify: bool,
+
+        #[arg(long, conflicts_with = "no_register", help = "Reinstall the package if the same version is already installed, removing files it no longer ships")]
+        reinstall: bool,
+
+        #[arg(long, value_name = "PATH", help = "Pin file to use instead of the preferences file in the config directory")]
+        pins: Option<PathBuf>,
     },
 
     #[command(alias = "u", about = "Uninstall a package (alias: u)")]
     Uninstall {
         // deb: ClioPath
-        deb: UninstallInput
+        deb: UninstallInput,
+
+        #[arg(long, help = "Warn about every installed file that's already gone instead of only counting them")]
+        strict: bool,
     },
 
     #[command(alias = "v", about = "View package info (alias: v)")]
     View {
-        deb: ClioPath
+        deb: ClioPath,
+
+        #[arg(long, value_name = "N", help = "Only show N levels of the file tree, collapsing deeper entries")]
+        depth: Option<usize>,
+
+        #[arg(long, help = "Show full control field values instead of truncating them")]
+        full: bool,
+
+        #[arg(long, value_enum, default_value_t = ViewFormat::Table, help = "How to print the package info")]
+        format: ViewFormat,
+
+        #[arg(long, conflicts_with = "format", help = "Also show how the package differs from the installed version of it")]
+        compare_installed: bool,
+
+        #[arg(long, value_name = "N", conflicts_with = "format", help = "Also list the N largest files in the package")]
+        top_files: Option<usize>,
+
+        #[arg(long, help = "Refuse control files that break deb822 rules instead of parsing them leniently")]
+        strict: bool,
+
+        #[arg(long, help = "Warn if Installed-Size doesn't match the size of the files in the package")]
+        verify: bool,
+    },
+
+    #[command(alias = "x", about = "Unpack a package's files into a directory without installing it (alias: x)")]
+    Extract {
+        deb: ClioPath,
+
+        #[arg(long, value_name = "DIR", conflicts_with = "flatten", help = "Directory to unpack into (default: <package>_<version>)")]
+        target_dir: Option<PathBuf>,
+
+        #[arg(long, help = "Unpack into the current directory instead")]
+        flatten: bool,
+
+        #[arg(long, help = "Unpack even if the directory or the files already exist")]
+        force: bool,
     },
 
     #[command(alias = "c", about = "Check if package is installed or not (alias: c)")]
     Check {
-        deb: ClioPath
+        deb: ClioPath,
+
+        #[arg(long, help = "Explain why the package isn't considered installed")]
+        explain: bool,
+
+        #[arg(long, value_name = "PATH", conflicts_with = "explain", help = "Instead check whether the package has PATH and whether installing it would overwrite an existing file")]
+        file: Option<PathBuf>,
     },
 
     #[command(alias = "a", about = "Fetches all installed packages (alias: a)")]
-    All,
+    All {
+        #[arg(long, value_enum, default_value_t = AllFormat::Table, help = "How to print the packages")]
+        format: AllFormat,
+
+        #[arg(long, value_name = "COLUMNS", value_delimiter = ',', value_parser = install::parse_column, help = "Comma separated columns to show, e.g. package,version,depends (default: package,version,architecture,description_short)")]
+        columns: Vec<String>,
+
+        #[arg(long, help = "Show every column (unless --columns is passed) with full values instead of truncating them")]
+        full: bool,
+
+        #[arg(long, conflicts_with_all = ["columns", "full"], help = "Count the installed packages per architecture instead of listing them")]
+        architecture_report: bool,
+    },
+
+    #[command(alias = "h", about = "Shows the history of installs and uninstalls (alias: h)")]
+    History {
+        #[arg(long, value_name = "DATE", value_parser = history::parse_since, help = "Only show operations from DATE on (YYYY-MM-DD or \"YYYY-MM-DD HH:MM:SS\", UTC)")]
+        since: Option<NaiveDateTime>,
+
+        #[arg(long, value_name = "DATE", value_parser = history::parse_until, help = "Only show operations up to and including DATE (YYYY-MM-DD or \"YYYY-MM-DD HH:MM:SS\", UTC)")]
+        until: Option<NaiveDateTime>,
+
+        #[arg(long, help = "Only show operations on PACKAGE")]
+        package: Option<String>,
+    },
+
+    #[command(alias = "f", about = "List the files an installed package added (alias: f)")]
+    Files {
+        package: String,
+
+        #[arg(long, value_enum, default_value_t = Format::Text, help = "How to print the file list")]
+        format: Format,
+
+        #[arg(short = '0', long, conflicts_with = "format", help = "End every path with a NUL byte instead of a newline, for xargs -0")]
+        null: bool,
+    },
+
+    #[command(alias = "o", about = "Find the installed package a file belongs to (alias: o)")]
+    Owner {
+        path: PathBuf,
+
+        #[arg(long, value_enum, default_value_t = Format::Text, help = "How to print the owner")]
+        format: Format,
+
+        #[arg(short = '0', long, conflicts_with = "format", help = "Print the path and the package each followed by a NUL byte instead of `path: package`")]
+        null: bool,
+    },
+
+    #[command(alias = "cl", about = "Show the Debian changelog of an installed package or a .deb (alias: cl)")]
+    Changelog {
+        #[arg(value_name = "PACKAGE|DEB")]
+        pkg: String,
+    },
+
+    #[command(alias = "cr", about = "Show the copyright file of an installed package or a .deb (alias: cr)")]
+    Copyright {
+        #[arg(value_name = "PACKAGE|DEB")]
+        pkg: String,
+    },
+
+    #[command(alias = "vf", about = "Check that the files of installed packages are still there (alias: vf)")]
+    Verify {
+        #[arg(required_unless_present = "all")]
+        package: Option<String>,
+
+        #[arg(long, conflicts_with = "package", help = "Verify every installed package")]
+        all: bool,
+    },
+
+    #[command(about = "Find files that more than one installed package claims")]
+    Audit {
+        #[arg(long, help = "Only keep the most recently installed package as the owner of each of those files")]
+        fix: bool,
+    },
 }
 
-fn main() {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+// sends every record to stderr and, with `--log-file`, also to a timestamped log file
+struct TeeLogger {
+    stderr: env_logger::Logger,
+    file: OnceLock<env_logger::Logger>,
+}
+
+static LOGGER: OnceLock<TeeLogger> = OnceLock::new();
+
+impl log::Log for TeeLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.stderr.enabled(metadata) || self.file.get().is_some_and(|file| file.enabled(metadata))
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.stderr.log(record);
+        if let Some(file) = self.file.get() {
+            file.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.stderr.flush();
+        if let Some(file) = self.file.get() {
+            file.flush();
+        }
+    }
+}
+
+fn init_logging(debug_sql: bool) {
+    let mut stderr = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+    if debug_sql {
+        stderr.filter_module("debby::sql", log::LevelFilter::Debug);
+    }
+    let stderr = stderr
         .format(|buf, record| {
             let level_color = match record.level() {
                 Level::Trace => "\x1b[90m",   // Bright black / gray
@@ -93,29 +350,78 @@ fn main() {
 
             writeln!(buf, "[{level_color}{}{reset}] ({}) {}", record.level(), record.target(), record.args())
         })
-        .init();
+        .build();
 
-    let cli = Cli::parse();
+    log::set_max_level(stderr.filter());
+    let logger = LOGGER.get_or_init(|| TeeLogger { stderr, file: OnceLock::new() });
+    log::set_logger(logger).expect("Failed to set logger");
+}
 
-    let dirs = match ProjectDirs::from("me", "illia", "debby") {
-        Some(dirs) => dirs,
-        None => {
-            error!("Failed to get project directories");
+// called after escalating, so the file is opened (and created) as the user debby actually runs as
+fn attach_log_file(path: &Path) {
+    let f = match fs::OpenOptions::new().create(true).append(true).open(path) {
+        Ok(f) => f,
+        Err(e) => {
+            error!("Failed to open log file {}: {}", path.display(), e);
             std::process::exit(1);
         }
     };
-    let db_path = dirs.data_dir().join("deb.sqlite");
 
-    trace!("db path: {:?}", db_path);
+    // the log file also gets debug records, e.g. every file touched by an install
+    let file = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("debug"))
+        .target(env_logger::Target::Pipe(Box::new(f)))
+        .write_style(env_logger::WriteStyle::Never)
+        .format(|buf, record| {
+            writeln!(buf, "{} [{}] ({}) {}", buf.timestamp(), record.level(), record.target(), record.args())
+        })
+        .build();
+
+    log::set_max_level(file.filter().max(log::max_level()));
+    if let Some(logger) = LOGGER.get() {
+        let _ = logger.file.set(file);
+    }
+}
+
+fn open_read_only(db_path: &Path, debug_sql: bool) -> Connection {
+    // nothing has been installed yet, an empty in-memory database behaves the same
+    // without creating anything on disk
+    let conn = if db_path.exists() {
+        db::open(db_path, debug_sql).and_then(|conn| match db::check_integrity(&conn) {
+            // a database from an older debby is upgraded in memory, the next install or uninstall upgrades the file
+            Ok(()) if db::user_version(&conn)? < db::LATEST_VERSION => {
+                db::copy_to_memory(&conn, debug_sql).and_then(|copy| db::migrate(&copy).map(|_| copy))
+            },
+            Ok(()) => Ok(conn),
+            Err(IntegrityError::Db(e)) => Err(e),
+            Err(IntegrityError::Corrupt(e)) => {
+                warn!("The database at {} is corrupt ({}), ignoring it. The next install or uninstall will back it up and start over", db_path.display(), e);
+                db::open(":memory:", debug_sql).and_then(|conn| db::migrate(&conn).map(|_| conn))
+            },
+        })
+    } else {
+        db::open(":memory:", debug_sql).and_then(|conn| db::migrate(&conn).map(|_| conn))
+    };
 
-    if let Some(parent) = db_path.parent() {
-        if let Err(e) = fs::create_dir_all(parent) {
-            error!("Failed to create data directory: {}", e);
+    // the sqlite3 crate always opens read-write (sqlite falls back to read-only for
+    // write-protected files), query_only makes sure nothing gets written either way
+    match conn.and_then(|conn| conn.execute("PRAGMA query_only = ON").map(|_| conn)) {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!("Failed to open sqlite connection: {}", e);
             std::process::exit(1);
         }
     }
+}
+
+fn open_read_write(db_path: &Path, debug_sql: bool) -> Connection {
+    if let Some(parent) = db_path.parent()
+        && let Err(e) = fs::create_dir_all(parent)
+    {
+        error!("Failed to create data directory: {}", e);
+        std::process::exit(1);
+    }
 
-    let conn = match Connection::open(&db_path) {
+    let open = || match db::open(db_path, debug_sql) {
         Ok(conn) => conn,
         Err(e) => {
             error!("Failed to open sqlite connection: {}", e);
@@ -123,63 +429,283 @@ fn main() {
         }
     };
 
-    if let Err(e) = conn.execute(
-        format!(
-            "CREATE TABLE IF NOT EXISTS debs (
-                id INTEGER PRIMARY KEY,
-                {},
-                installed TEXT
-            )",
-            Control::sql_fields()
-        )
-    ) {
-        error!("Failed to create table: {}", e);
+    let mut conn = open();
+
+    // a corrupt database makes every query fail, keep it around for manual recovery and start over.
+    // anything else (e.g. another debby still holding the lock after the busy timeout) is only reported
+    match db::check_integrity(&conn) {
+        Ok(()) => {},
+        Err(IntegrityError::Db(e)) => {
+            error!("Failed to check the database at {}: {}", db_path.display(), e);
+            std::process::exit(1);
+        },
+        Err(IntegrityError::Corrupt(e)) => {
+            drop(conn);
+
+            let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            let backup = PathBuf::from(format!("{}.corrupt-{}", db_path.display(), secs));
+
+            if let Err(e) = fs::rename(db_path, &backup) {
+                error!("The database at {} is corrupt and couldn't be moved out of the way: {}", db_path.display(), e);
+                std::process::exit(1);
+            }
+            // a leftover journal belongs to the corrupt database, not the new one
+            let _ = fs::rename(format!("{}-journal", db_path.display()), format!("{}-journal", backup.display()));
+
+            warn!("The database at {} was corrupt ({}), it was moved to {} and a new one was created", db_path.display(), e, backup.display());
+            conn = open();
+        },
+    }
+
+    if let Err(e) = db::migrate(&conn) {
+        error!("Failed to migrate database: {}", e);
+        std::process::exit(1);
+    }
+
+    conn
+}
+
+fn create_output(path: OutputPath) -> Output {
+    match path.create() {
+        Ok(out) => out,
+        Err(e) => {
+            error!("Failed to open output: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn finish_output(out: Output) {
+    if let Err(e) = out.finish() {
+        error!("Failed to write output: {}", e);
+        std::process::exit(1);
+    }
+}
+
+// a directory set through an environment variable, which has to exist or be creatable so a typo doesn't go unnoticed
+fn env_dir(var: &str) -> Option<PathBuf> {
+    let dir = PathBuf::from(std::env::var_os(var).filter(|dir| !dir.is_empty())?);
+
+    if let Err(e) = fs::create_dir_all(&dir) {
+        error!("Failed to create {} ({}): {}", dir.display(), var, e);
+        std::process::exit(1);
+    }
+
+    Some(dir)
+}
+
+// only goes through sudo when actually unprivileged, so running as root in a chroot or
+// container without a sudo binary works. with --no-sudo permission errors are left to happen naturally
+fn escalate(no_sudo: bool) {
+    if no_sudo || sudo::check() != sudo::RunningAs::User {
+        return;
+    }
+
+    // sudo resets the environment, keep the variables the paths are resolved from
+    if let Err(e) = sudo::with_env(&["DEBBY_", "XDG_"]) {
+        error!("Failed to escalate to root: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    init_logging(cli.debug_sql);
+    cli.color.apply();
+
+    let quiet = cli.quiet || !cli.progress.enabled();
+    let prompter = Prompter::new(cli.yes, cli.no);
+    let limits = Limits { max_uncompressed_size: cli.max_uncompressed_size, max_entries: cli.max_entries };
+
+    if let Some(template) = &cli.progress_template
+        && let Err(e) = extract::check_template(template)
+    {
+        error!("Invalid --progress-template: {}", e);
         std::process::exit(1);
     }
 
+    // only extracting and viewing work on files the user can read anyway. everything else escalates before any
+    // path is resolved or created, so that happens once as the user debby actually runs as. comparing with the
+    // installed version reads the same database installs write, so that escalates too
+    if !matches!(cli.cmd, Commands::Extract { .. } | Commands::View { compare_installed: false, .. }) {
+        escalate(cli.no_sudo);
+    }
+
+    if let Some(log_file) = &cli.log_file {
+        attach_log_file(log_file);
+    }
+
+    // ProjectDirs needs a home directory, which minimal containers and CI runners might not have
+    let dirs = ProjectDirs::from("me", "illia", "debby");
+    let xdg_dir = |var: &str| std::env::var_os(var).filter(|dir| !dir.is_empty()).map(|dir| PathBuf::from(dir).join("debby"));
+
+    // flags win over the environment, which wins over the default directories
+    let db_path = match cli.db.clone()
+        .or_else(|| env_dir("DEBBY_DATA_DIR").map(|dir| dir.join("deb.sqlite")))
+        .or_else(|| dirs.as_ref().map(|dirs| dirs.data_dir().join("deb.sqlite")))
+        .or_else(|| xdg_dir("XDG_DATA_HOME").map(|dir| dir.join("deb.sqlite")))
+    {
+        Some(db_path) => db_path,
+        None => {
+            error!("Failed to find a data directory, set XDG_DATA_HOME or pass --db");
+            std::process::exit(1);
+        }
+    };
+
+    let cache_dir = match cli.cache.clone()
+        .or_else(|| env_dir("DEBBY_CACHE_DIR"))
+        .or_else(|| dirs.as_ref().map(|dirs| dirs.cache_dir().to_path_buf()))
+        .or_else(|| xdg_dir("XDG_CACHE_HOME"))
+    {
+        Some(cache_dir) => cache_dir,
+        None => {
+            error!("Failed to find a cache directory, set XDG_CACHE_HOME or pass --cache");
+            std::process::exit(1);
+        }
+    };
+
+    trace!("db path: {:?}", db_path);
+
+    // query commands never write, so they shouldn't need a writable database (or data directory),
+    // the same goes for anything that's only simulated
+    let read_only = cli.simulate || matches!(cli.cmd, Commands::View { .. } | Commands::Extract { .. } | Commands::Check { .. } | Commands::All { .. } | Commands::History { .. } | Commands::Verify { .. } | Commands::Audit { fix: false } | Commands::Files { .. } | Commands::Owner { .. } | Commands::Changelog { .. } | Commands::Copyright { .. });
+
+    let conn = if read_only {
+        open_read_only(&db_path, cli.debug_sql)
+    } else {
+        open_read_write(&db_path, cli.debug_sql)
+    };
+
     match cli.cmd {
-        Commands::Install { deb } => {
-            if let Err(e) = sudo::escalate_if_needed() {
-                error!("Failed to escalate to root: {}", e);
-                std::process::exit(1);
-            }
+        Commands::Install { deb, assume_installed, prefix_map, require_signature, keyring, dereference, no_setuid, safe, allow_dangerous, deny_paths, no_overwrite_newer, force_overwrite, no_register, verify, reinstall, pins } => {
+            let pins_path = pins
+                .or_else(|| dirs.as_ref().map(|dirs| dirs.config_dir().join("preferences")))
+                .or_else(|| xdg_dir("XDG_CONFIG_HOME").map(|dir| dir.join("preferences")));
+            let pins = match pins_path.as_deref().map(Pins::load) {
+                Some(Ok(pins)) => pins,
+                Some(Err(e)) => {
+                    error!("Failed to read pins from {}", e);
+                    std::process::exit(1);
+                },
+                None => Pins::default(),
+            };
 
-            install::install(deb, dirs, conn, cli.verbose)
+            install::install(deb, &cache_dir, conn, InstallOptions {
+                verbose: cli.verbose,
+                quiet,
+                progress_unit: cli.progress_unit,
+                progress_template: cli.progress_template,
+                limits,
+                assume_installed,
+                prefix_map,
+                require_signature,
+                keyring,
+                dereference,
+                no_setuid,
+                safe,
+                allow_dangerous,
+                deny_paths,
+                no_overwrite_newer,
+                force_overwrite,
+                no_register,
+                verify,
+                reinstall,
+                pins,
+                simulate: cli.simulate,
+                prompter,
+            })
         },
-        Commands::Uninstall { deb } => {
-            if let Err(e) = sudo::escalate_if_needed() {
-                error!("Failed to escalate to root: {}", e);
-                std::process::exit(1);
-            }
+        Commands::Uninstall { deb, strict } => {
+            let opts = UninstallOptions { verbose: cli.verbose, quiet, strict, simulate: cli.simulate, prompter };
 
             match deb {
                 UninstallInput::Path(clio_path) => {
-                    install::uninstall(clio_path, dirs, conn, cli.verbose)
+                    install::uninstall(clio_path, &cache_dir, conn, opts)
                 },
                 UninstallInput::PackageName(pkg_name) => {
-                    install::uninstall_by_pkg_name(pkg_name, conn, cli.verbose);
+                    install::uninstall_by_pkg_name(pkg_name, conn, opts);
                 },
                 UninstallInput::Id(id) => {
-                    install::uninstall_by_id(id, conn, cli.verbose);
+                    install::uninstall_by_id(id, conn, opts);
                 },
             }
         },
-        Commands::Check { deb } => {
-            if let Err(e) = sudo::escalate_if_needed() {
-                error!("Failed to escalate to root: {}", e);
-                std::process::exit(1);
+        Commands::Check { deb, explain, file } => {
+            install::is_installed(deb, &cache_dir, conn, quiet, explain, file, limits)
+        },
+        Commands::All { format, columns, full, architecture_report } => {
+            let mut out = create_output(cli.output);
+            if architecture_report {
+                install::architecture_report(conn, format, &mut out);
+            } else {
+                install::all(conn, &columns, full, format, &mut out);
             }
+            finish_output(out);
+        },
+        Commands::History { since, until, package } => {
+            let mut out = create_output(cli.output);
+            history::history(conn, since, until, package, &mut out);
+            finish_output(out);
+        },
+        Commands::Files { package, format, null } => {
+            let mut out = create_output(cli.output);
+            files::files(conn, package, if null { Format::Null } else { format }, &mut out);
+            finish_output(out);
+        },
+        Commands::Changelog { pkg } => {
+            let mut out = create_output(cli.output);
+            doc::show(conn, &pkg, "changelog", doc::CHANGELOG, limits, &mut out);
+            finish_output(out);
+        },
+        Commands::Copyright { pkg } => {
+            let mut out = create_output(cli.output);
+            doc::show(conn, &pkg, "copyright file", doc::COPYRIGHT, limits, &mut out);
+            finish_output(out);
+        },
+        Commands::Owner { path, format, null } => {
+            let mut out = create_output(cli.output);
+            let owned = files::owner(conn, &path, if null { Format::Null } else { format }, &mut out);
+            finish_output(out);
 
-            install::is_installed(deb, dirs, conn)
+            if !owned {
+                std::process::exit(1);
+            }
         },
-        Commands::All => {
-            if let Err(e) = sudo::escalate_if_needed() {
-                error!("Failed to escalate to root: {}", e);
+        Commands::Verify { package, all: _ } => {
+            if !verify::verify(conn, package) {
                 std::process::exit(1);
             }
+        },
+        Commands::Audit { fix } => {
+            let mut out = create_output(cli.output);
+            let clean = audit::audit(conn, fix, cli.simulate, prompter, &mut out);
+            finish_output(out);
 
-            install::all(conn)
+            if !clean {
+                std::process::exit(1);
+            }
         },
-        Commands::View { deb } => view::view(deb, dirs),
+        Commands::Extract { deb, target_dir, flatten, force } => {
+            unpack::unpack(deb, UnpackOptions { target_dir, flatten, force, quiet, limits });
+        },
+        Commands::View { deb, depth, full, format, compare_installed, top_files, strict, verify } => {
+            let mut out = create_output(cli.output);
+            view::view(deb, &cache_dir, conn, ViewOptions { quiet, depth, full, format, compare_installed, top_files, strict, verify, limits }, &mut out);
+            finish_output(out);
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uninstall_input_recognizes_uppercase_deb_paths() {
+        assert!(matches!("hello.deb".parse::<UninstallInput>(), Ok(UninstallInput::Path(_))));
+        assert!(matches!("HELLO.DEB".parse::<UninstallInput>(), Ok(UninstallInput::Path(_))));
+        assert!(matches!("hello".parse::<UninstallInput>(), Ok(UninstallInput::PackageName(name)) if name == "hello"));
+        assert!(matches!("42".parse::<UninstallInput>(), Ok(UninstallInput::Id(42))));
     }
 }
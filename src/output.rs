@@ -0,0 +1,38 @@
+use std::io::Write;
+
+use clio::Output;
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    /// Plain text, one entry per line
+    Text,
+    Json,
+    /// Every entry ends with a NUL byte, like `find -print0`
+    #[value(skip)]
+    Null,
+}
+
+/// Writes `value` to `out` as a single line of JSON
+pub fn write_json<T: Serialize>(out: &mut Output, value: &T) {
+    serde_json::to_writer(&mut *out, value).expect("Failed to write JSON");
+    writeln!(out).expect("Failed to write JSON");
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum ColorMode {
+    /// Only when stdout is a terminal and NO_COLOR isn't set
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    pub fn apply(self) {
+        match self {
+            ColorMode::Auto => {},
+            ColorMode::Always => colored::control::set_override(true),
+            ColorMode::Never => colored::control::set_override(false),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::{cmp::Ordering, path::Path};
+
+use log::{error, warn};
+use sqlite3::Connection;
+
+use crate::{control::{self, Control, ControlWithData}, deps};
+
+/// The priority of every package version that isn't pinned, same as apt's
+pub const DEFAULT_PRIORITY: i32 = 500;
+/// The priority of a pin without a `Pin-Priority`, same as apt's for the target release
+pub const PIN_PRIORITY: i32 = 990;
+/// From this priority on a pinned version may replace a newer one, like with apt
+pub const DOWNGRADE_PRIORITY: i32 = 1000;
+
+#[derive(Clone, Debug)]
+struct Pin {
+    package: String,
+    version: String,
+    priority: i32,
+}
+
+/// Pins from an apt_preferences(5)-like file, e.g.
+/// ```text
+/// Package: curl
+/// Pin: version 8.5.0-2
+/// Pin-Priority: 1001
+/// ```
+/// Only exact versions can be pinned so far
+#[derive(Clone, Debug, Default)]
+pub struct Pins {
+    pins: Vec<Pin>,
+}
+
+impl Pins {
+    /// Reads the pins from `path`, a missing file just means there are none
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(s) => Self::parse(&s).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut pins = vec![];
+
+        // one pin per paragraph, lines starting with # are comments
+        let mut paragraphs = vec![vec![]];
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() {
+                paragraphs.push(vec![]);
+            } else if !line.starts_with('#') {
+                paragraphs.last_mut().expect("there's always a paragraph").push(line);
+            }
+        }
+
+        for paragraph in paragraphs {
+            let (mut package, mut version, mut priority) = (None, None, PIN_PRIORITY);
+
+            for line in paragraph {
+                let Some((key, value)) = line.split_once(':') else {
+                    return Err(format!("{:?} isn't a `Field: value` line", line));
+                };
+                let value = value.trim();
+
+                match control::normalize_field_name(key).as_str() {
+                    "package" => package = Some(control::normalize_package_name(value)),
+                    "pin" => match value.strip_prefix("version ") {
+                        Some(v) => version = Some(v.trim().to_string()),
+                        None => return Err(format!("unsupported pin {:?}, only `Pin: version <version>` is", value)),
+                    },
+                    "pin_priority" => priority = value.parse().map_err(|_| format!("{:?} isn't a priority", value))?,
+                    // e.g. Explanation
+                    _ => {},
+                }
+            }
+
+            match (package, version) {
+                (Some(package), Some(version)) => pins.push(Pin { package, version, priority }),
+                (None, None) => {},
+                _ => return Err("every pin needs a Package and a Pin".to_string()),
+            }
+        }
+
+        Ok(Pins { pins })
+    }
+
+    /// The priority of a package version, the first pin for it wins
+    pub fn priority(&self, package: &str, version: &str) -> i32 {
+        let package = control::normalize_package_name(package);
+
+        self.pins.iter()
+            .find(|pin| pin.package == package && deps::compare_versions(&pin.version, version) == Ordering::Equal)
+            .map_or(DEFAULT_PRIORITY, |pin| pin.priority)
+    }
+}
+
+/// Checks installing `ctrl` against the pins, exits when a pinned package would lose out to it.
+/// Returns how many problems are worth asking about, e.g. a downgrade
+pub fn check(conn: &Connection, ctrl: &Control, conflicts: &[String], pins: &Pins) -> usize {
+    let priority = pins.priority(&ctrl.package, &ctrl.version);
+    let mut problems = 0;
+
+    if let Ok(installed) = ControlWithData::latest(conn, &ctrl.package) {
+        let installed = installed.ctrl;
+        let installed_priority = pins.priority(&installed.package, &installed.version);
+
+        if installed_priority > priority {
+            error!("{} {} is pinned with priority {}, refusing to replace it with {} (priority {})",
+                installed.package, installed.version, installed_priority, ctrl.version, priority);
+            std::process::exit(1);
+        }
+
+        if deps::compare_versions(&ctrl.version, &installed.version) == Ordering::Less && priority < DOWNGRADE_PRIORITY {
+            warn!("Installing {} {} would downgrade it from {}", ctrl.package, ctrl.version, installed.version);
+            problems += 1;
+        }
+    }
+
+    for package in conflicts {
+        let Ok(installed) = ControlWithData::latest(conn, package) else { continue };
+        let installed_priority = pins.priority(&installed.ctrl.package, &installed.ctrl.version);
+
+        if installed_priority > priority {
+            error!("{} conflicts with {} {}, which is pinned with priority {} (vs. {}), refusing to install it",
+                ctrl.package, installed.ctrl.package, installed.ctrl.version, installed_priority, priority);
+            std::process::exit(1);
+        }
+    }
+
+    problems
+}
@@ -0,0 +1,60 @@
+use std::io::{BufRead, IsTerminal, Write};
+
+use log::info;
+
+#[derive(Clone, Copy, Debug)]
+enum Mode {
+    Ask,
+    Yes,
+    No,
+}
+
+/// Asks yes/no questions, unless `--yes`/`--no` was passed or there's no terminal to ask on,
+/// in which case every question gets the same answer without blocking
+#[derive(Clone, Copy, Debug)]
+pub struct Prompter {
+    mode: Mode,
+}
+
+impl Prompter {
+    pub fn new(yes: bool, no: bool) -> Self {
+        let mode = if yes {
+            Mode::Yes
+        } else if no {
+            Mode::No
+        } else {
+            Mode::Ask
+        };
+
+        Prompter { mode }
+    }
+
+    /// Asks `question`, `default` is what pressing enter (or running non-interactively) answers
+    pub fn confirm(&self, question: &str, default: bool) -> bool {
+        match self.mode {
+            Mode::Yes => return true,
+            Mode::No => return false,
+            Mode::Ask => {},
+        }
+
+        if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+            info!("{} {}", question, if default { "yes (not interactive)" } else { "no (not interactive)" });
+            return default;
+        }
+
+        let mut stderr = std::io::stderr();
+        let _ = write!(stderr, "{} [{}] ", question, if default { "Y/n" } else { "y/N" });
+        let _ = stderr.flush();
+
+        let mut answer = String::new();
+        if std::io::stdin().lock().read_line(&mut answer).is_err() {
+            return default;
+        }
+
+        match answer.trim().to_lowercase().as_str() {
+            "y" | "yes" => true,
+            "n" | "no" => false,
+            _ => default,
+        }
+    }
+}
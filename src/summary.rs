@@ -0,0 +1,43 @@
+use colored::Colorize;
+use log::debug;
+
+/// Counts what an install or uninstall did with each file, printed as one line at the end
+pub struct Summary {
+    unit: &'static str,
+    pub done: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+impl Summary {
+    pub fn new(unit: &'static str) -> Self {
+        Summary { unit, done: 0, skipped: 0, failed: 0 }
+    }
+
+    /// Prints e.g. `Installed curl 8.0: 12 files/directories, 1 skipped`. It's green when everything went fine,
+    /// yellow when files were skipped and red when files failed or the operation was cut short (`partial`)
+    pub fn print(&self, headline: &str, partial: bool) {
+        let mut line = format!("{}: {} {}", headline, self.done, self.unit);
+        if self.skipped > 0 {
+            line.push_str(&format!(", {} skipped", self.skipped));
+        }
+        if self.failed > 0 {
+            line.push_str(&format!(", {} failed", self.failed));
+        }
+        if partial {
+            line.push_str(", incomplete");
+        }
+
+        // the log file gets it without colors
+        debug!("{}", line);
+
+        let line = if partial || self.failed > 0 {
+            line.red()
+        } else if self.skipped > 0 {
+            line.yellow()
+        } else {
+            line.green()
+        };
+        eprintln!("{}", line.bold());
+    }
+}
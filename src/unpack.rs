@@ -0,0 +1,103 @@
+use std::path::PathBuf;
+
+use clio::ClioPath;
+use log::{error, warn};
+
+use crate::{control, extract::{self, MixedEta, Limits, ProgressUnit}, summary::Summary};
+
+pub struct UnpackOptions {
+    pub target_dir: Option<PathBuf>,
+    pub flatten: bool,
+    pub force: bool,
+    pub quiet: bool,
+    pub limits: Limits,
+}
+
+/// Unpacks the files of a package into a directory without installing them, like `dpkg-deb -x`
+pub fn unpack(deb: ClioPath, opts: UnpackOptions) {
+    let UnpackOptions { target_dir, flatten, force, quiet, limits } = opts;
+
+    if !deb.exists() {
+        error!("Failed to extract .deb file because the .deb file you specified does not exist.");
+        std::process::exit(-1);
+    }
+
+    if !extract::is_deb(&deb) {
+        error!("Failed to extract .deb file because the file you specified isn't one.");
+        std::process::exit(-1);
+    }
+
+    extract::check_members(&deb);
+
+    let Some(ctrl_str) = extract::extract_control(extract::open(&deb)) else {
+        error!("Failed to get control file from .deb, make sure the .deb is valid");
+        std::process::exit(-1);
+    };
+    let ctrl = match control::parse_control(ctrl_str) {
+        Ok(ctrl) => ctrl,
+        Err(e) => {
+            error!("Failed to parse control file: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let (paths, data_bytes) = extract::data_paths_with_size(extract::open(&deb), limits)
+        .unwrap_or_else(|e| extract::extract_failed(&deb, e));
+
+    // the current directory always exists, so only refuse to overwrite files that are already in it
+    let target = if flatten {
+        let target = PathBuf::from(".");
+        if !force && let Some((path, _)) = paths.iter().find(|(path, is_dir)| !is_dir && target.join(path).symlink_metadata().is_ok()) {
+            error!("{} already exists, pass --force to overwrite it", path.display());
+            std::process::exit(1);
+        }
+        target
+    } else {
+        let target = target_dir.unwrap_or_else(|| PathBuf::from(format!("{}_{}", ctrl.package, ctrl.version)));
+        if !force && target.symlink_metadata().is_ok() {
+            error!("{} already exists, pass --force to extract into it anyway", target.display());
+            std::process::exit(1);
+        }
+        target
+    };
+
+    if let Err(e) = std::fs::create_dir_all(&target) {
+        error!("Failed to create {}: {}", target.display(), e);
+        std::process::exit(1);
+    }
+
+    let eta = MixedEta::new(data_bytes);
+    let bar = extract::progress_bar(if quiet { 0 } else { paths.len() as u64 }, ProgressUnit::Files, None, quiet, &eta);
+    let mut summary = Summary::new("files/directories");
+
+    let result = extract::for_each_data_entry(extract::open(&deb), limits, |entry| {
+        bar.inc(1);
+        eta.inc(entry.size());
+
+        // the root itself, or a path that tries to escape it
+        if entry.path().ok().and_then(|path| extract::package_path(&path)).is_none() {
+            return;
+        }
+
+        match entry.unpack_in(&target) {
+            Ok(true) => summary.done += 1,
+            Ok(false) => summary.skipped += 1,
+            Err(e) => {
+                warn!("Failed to extract {}: {}, skipping...", entry.path().map_or_else(|_| "entry".to_string(), |path| path.display().to_string()), e);
+                summary.failed += 1;
+            },
+        }
+    });
+
+    bar.finish_and_clear();
+
+    let failed = result.is_err();
+    if let Err(e) = result {
+        error!("Failed to extract {}: {}, it was only partially extracted", deb.display(), e);
+    }
+
+    summary.print(&format!("Extracted {} {} to {}", ctrl.package, ctrl.version, target.display()), failed);
+    if failed || summary.failed > 0 {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,72 @@
+use std::path::PathBuf;
+
+use log::{info, warn};
+use sqlite3::{Connection, State};
+
+use crate::control;
+
+/// Checks that every file recorded for a package (or every installed package with `package = None`) still exists,
+/// returns whether nothing is missing
+pub fn verify(conn: Connection, package: Option<String>) -> bool {
+    let mut stmt = match &package {
+        Some(package) => {
+            let mut stmt = conn.prepare("SELECT package, version, installed FROM debs WHERE lower(package) = ? ORDER BY id")
+                .expect("Failed to prepare statement");
+            stmt.bind(1, control::normalize_package_name(package).as_str()).expect("Failed to bind package name to prepared statement");
+            stmt
+        },
+        None => conn.prepare("SELECT package, version, installed FROM debs ORDER BY id").expect("Failed to prepare statement"),
+    };
+
+    let mut checked = 0;
+    let mut broken = vec![];
+
+    while stmt.next().expect("Failed to get row") == State::Row {
+        let name = stmt.read::<String>(0).unwrap_or_default();
+        let version = stmt.read::<String>(1).unwrap_or_default();
+        let installed = stmt.read::<String>(2).unwrap_or_default();
+
+        let missing = missing_files(&installed);
+        checked += 1;
+
+        if missing.is_empty() {
+            info!("{} {}: ok", name, version);
+            continue;
+        }
+
+        for path in &missing {
+            warn!("{} {}: {} is missing", name, version, path.display());
+        }
+        broken.push(format!("{} {}", name, version));
+    }
+
+    if checked == 0 {
+        return match package {
+            Some(package) => {
+                warn!("{} is not installed", package);
+                false
+            },
+            None => {
+                info!("No packages installed yet.");
+                true
+            },
+        };
+    }
+
+    if broken.is_empty() {
+        info!("All {} package(s) are intact", checked);
+        true
+    } else {
+        warn!("{} of {} package(s) are missing files: {}", broken.len(), checked, broken.join(", "));
+        false
+    }
+}
+
+// the recorded paths that are gone, a dangling symlink still counts as present
+fn missing_files(installed: &str) -> Vec<PathBuf> {
+    installed.split(',')
+        .filter(|s| !s.is_empty())
+        .map(|s| PathBuf::from(s.trim()))
+        .filter(|path| path.symlink_metadata().is_err())
+        .collect()
+}
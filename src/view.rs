@@ -1,43 +1,95 @@
-use std::{fs::File, io::{Cursor, Seek}};
+use std::{collections::BTreeSet, io::Write, path::Path};
 
-use cli_table::{Cell, CellStruct, Table};
-use clio::ClioPath;
-use directories::ProjectDirs;
-use log::{error, info};
+use cli_table::{Cell, CellStruct, Color, ColorChoice, Style, Table, TableStruct};
+use clio::{ClioPath, Output};
+use colored::Colorize;
+use indicatif::HumanBytes;
+use log::{error, info, warn};
+use sqlite3::Connection;
 
-use crate::{control::{self, Control}, extract};
+use crate::{control::{self, Control, ControlWithData}, extract::{self, Limits}};
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum ViewFormat {
+    /// Control fields as a table followed by the file tree
+    Table,
+    /// Only the control fields, exactly like a control file
+    Deb822,
+}
+
+pub struct ViewOptions {
+    pub quiet: bool,
+    pub depth: Option<usize>,
+    pub full: bool,
+    pub format: ViewFormat,
+    pub compare_installed: bool,
+    pub top_files: Option<usize>,
+    pub strict: bool,
+    pub verify: bool,
+    pub limits: Limits,
+}
+
+pub fn view(deb: ClioPath, cache_dir: &Path, conn: Connection, opts: ViewOptions, out: &mut Output) {
+    let ViewOptions { quiet, depth, full, format, compare_installed, top_files, strict, verify, limits } = opts;
 
-pub fn view(deb: ClioPath, dirs: ProjectDirs) {
     if !deb.exists() {
         error!("Failed to view .deb file because the .deb file you specified does not exist.");
         std::process::exit(-1);
     }
 
-    if deb.extension().is_none_or(|ext| ext != "deb") {
+    if !extract::is_deb(&deb) {
         error!("Failed to view .deb file because the file you specified isn't one.");
         std::process::exit(-1);
     }
 
-    let mut f = File::open(deb.to_path_buf()).unwrap();
+    extract::check_members(&deb);
 
-    let cache_dir = dirs.cache_dir();
     let extract_dir = cache_dir.join("extracted");
 
     let _ = std::fs::remove_dir_all(&extract_dir);
 
-    let ctrl_str = extract::extract_control(f.try_clone().expect("Failed to clone file")).expect("Failed to extract control");
+    let spinner = extract::spinner("Reading package metadata...", quiet);
+    let Some(ctrl_str) = extract::extract_control(extract::open(&deb)) else {
+        spinner.finish_and_clear();
+        error!("Failed to get control file from .deb, make sure the .deb is valid");
+        std::process::exit(-1);
+    };
+    if strict && let Err((line, e)) = control::check_deb822(&ctrl_str) {
+        spinner.finish_and_clear();
+        error!("The control file isn't valid deb822, line {}: {}", line, e);
+        std::process::exit(1);
+    }
     let ctrl = match control::parse_control(ctrl_str) {
         Ok(ctrl) => ctrl,
         Err(e) => {
+            spinner.finish_and_clear();
             error!("Failed to parse control file: {}", e);
             std::process::exit(1);
         }
     };
+    spinner.finish_and_clear();
 
-    f.seek(std::io::SeekFrom::Start(0)).unwrap();
+    if let Some(problem) = ctrl.maintainer_problem() {
+        warn!("Malformed Maintainer field {:?}: {}", ctrl.maintainer, problem);
+    }
+
+    if verify {
+        let (paths, data_bytes) = extract::data_paths_with_size(extract::open(&deb), limits)
+            .unwrap_or_else(|e| extract::extract_failed(&deb, e));
+        if let Some(problem) = ctrl.installed_size_problem(data_bytes, paths.len()) {
+            warn!("Installed-Size looks wrong, the package may be malformed or repacked: {}", problem);
+        }
+    }
 
-    let tree = extract::extract_files_tree(f);
+    if let ViewFormat::Deb822 = format {
+        write!(out, "{}", control::to_deb822(&ctrl)).expect("Failed to write control fields");
+        return;
+    }
+
+    let tree = extract::extract_files_tree(extract::open(&deb), depth, limits)
+        .unwrap_or_else(|e| extract::extract_failed(&deb, e));
     let mut table: Vec<Vec<CellStruct>> = vec![];
+    let links = supports_links(out);
 
     for field in Control::fields() {
         let val = if let Some(val) = ctrl.field(field.as_str()) {
@@ -45,34 +97,181 @@ pub fn view(deb: ClioPath, dirs: ProjectDirs) {
         } else {
             continue;
         };
-        let val = if val == "NULL".to_string() {
+        let val = if val == "NULL" {
             continue;
+        } else if full {
+            wrap(&val, 80)
         } else {
             truncate(&val, 50)
         };
 
+        // `Name <email>` is shown as two rows
+        if field == "maintainer" {
+            let (name, email) = ctrl.maintainer_parts();
+            table.push(vec![field.clone().cell(), format!("'{}'", name).cell()]);
+            if let Some(email) = email {
+                table.push(vec!["maintainer email".cell(), format!("'{}'", email).cell()]);
+            }
+            continue;
+        }
+
+        let url = match field.as_str() {
+            "homepage" => ctrl.homepage.as_deref(),
+            "bugs" => ctrl.bugs.as_deref(),
+            _ => None,
+        };
+        let val = match url {
+            Some(url) if links && (url.starts_with("http://") || url.starts_with("https://")) => hyperlink(url, &val),
+            _ => val,
+        };
+
         table.push(vec![field.clone().cell(), val.cell()]);
     }
 
     info!("control:");
-    cli_table::print_stdout(table.table()).expect("Failed to print table of control fields");
+    print_table(out, table.table());
     info!("files:");
 
-    let mut buf = Cursor::new(Vec::new());
+    // written to the output rather than logged because i dont want a prefix when printing the file tree
+    ptree::write_tree(&tree, &mut *out).expect("Failed to write file tree");
+    writeln!(out).expect("Failed to write file tree");
 
-    ptree::write_tree(&tree, &mut buf).expect("Failed to write file tree");
+    if let Some(n) = top_files {
+        let largest = extract::largest_files(extract::open(&deb), n, limits)
+            .unwrap_or_else(|e| extract::extract_failed(&deb, e));
 
-    let out = String::from_utf8(buf.into_inner()).expect("invalid UTF-8");
+        let table: Vec<Vec<CellStruct>> = largest.into_iter()
+            .map(|(path, size)| vec![HumanBytes(size).to_string().cell(), Path::new("/").join(path).display().cell()])
+            .collect();
 
-    // the only usage of println in this project JUST BECAUSE i dont want a prefix when priting
-    // file tree
-    println!("{}", out);
+        info!("largest files:");
+        print_table(out, table.table().title(vec!["size".cell().bold(true), "path".cell().bold(true)]));
+    }
+
+    if compare_installed {
+        compare(&conn, &ctrl, &deb, limits, out);
+    }
 }
 
+// shows how a package differs from the installed version of it, field by field and file by file
+fn compare(conn: &Connection, ctrl: &Control, deb: &Path, limits: Limits, out: &mut Output) {
+    let installed = match ControlWithData::latest(conn, &ctrl.package) {
+        Ok(installed) => installed,
+        Err(_) => {
+            info!("{} is not installed, nothing to compare against", ctrl.package);
+            return;
+        }
+    };
+
+    let mut table: Vec<Vec<CellStruct>> = vec![];
+
+    for ((field, new), (_, old)) in ctrl.values().into_iter().zip(installed.ctrl.values()) {
+        if new.is_none() && old.is_none() {
+            continue;
+        }
+
+        let changed = new != old;
+        let (new, old) = (truncate(&new.unwrap_or_default(), 50), truncate(&old.unwrap_or_default(), 50));
+
+        let row = vec![field.cell(), old.cell(), new.cell()];
+        table.push(if changed {
+            row.into_iter().map(|cell| cell.bold(true).foreground_color(Some(Color::Yellow))).collect()
+        } else {
+            row
+        });
+    }
+
+    info!("compared to installed {} {}:", installed.ctrl.package, installed.ctrl.version);
+    print_table(out, table.table().title(vec![
+        "field".cell().bold(true),
+        "installed".cell().bold(true),
+        "package".cell().bold(true),
+    ]));
+
+    // same paths as an install without --prefix-map would record
+    let new_files: BTreeSet<String> = extract::data_paths(extract::open(deb), limits)
+        .unwrap_or_else(|e| extract::extract_failed(deb, e))
+        .into_iter()
+        .map(|(path, _)| Path::new("/").join(path).display().to_string())
+        .collect();
+    let old_files: BTreeSet<String> = installed.installed.split(',')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.trim().to_string())
+        .collect();
+
+    info!("files:");
+    if new_files == old_files {
+        writeln!(out, "no differences").expect("Failed to write file list");
+    }
+    for path in new_files.difference(&old_files) {
+        writeln!(out, "{} {}", "+".green(), path).expect("Failed to write file list");
+    }
+    for path in old_files.difference(&new_files) {
+        writeln!(out, "{} {}", "-".red(), path).expect("Failed to write file list");
+    }
+}
+
+// OSC 8 links are only written to terminals that are allowed colors, since anything else would show the escape codes
+fn supports_links(out: &Output) -> bool {
+    out.is_tty()
+        && colored::control::SHOULD_COLORIZE.should_colorize()
+        && std::env::var("TERM").is_ok_and(|term| !term.is_empty() && term != "dumb")
+}
+
+/// Makes `text` a clickable link to `url` in terminals that support OSC 8 hyperlinks
+fn hyperlink(url: &str, text: &str) -> String {
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text)
+}
+
+/// Prints a table to `out`, only keeping colors when writing to a terminal
+pub fn print_table(out: &mut Output, table: TableStruct) {
+    let color_choice = if out.is_tty() { ColorChoice::Always } else { ColorChoice::Never };
+    let display = table.color_choice(color_choice).display().expect("Failed to render table");
+
+    writeln!(out, "{}", display).expect("Failed to print table");
+}
+
+// `max_len` counts characters, slicing at a byte offset could split one
 pub fn truncate(s: &str, max_len: usize) -> String {
-    if s.len() > max_len {
-        format!("{}...", &s[..max_len])
-    } else {
-        s.to_string()
+    match s.char_indices().nth(max_len) {
+        Some((end, _)) => format!("{}...", &s[..end]),
+        None => s.to_string(),
+    }
+}
+
+/// Wraps every line of `s` at word boundaries so that it's at most `width` characters wide
+pub fn wrap(s: &str, width: usize) -> String {
+    let mut wrapped = Vec::new();
+
+    for line in s.lines() {
+        let mut current = String::new();
+
+        for word in line.split(' ') {
+            if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > width {
+                wrapped.push(std::mem::take(&mut current));
+            }
+
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(word);
+        }
+
+        wrapped.push(current);
+    }
+
+    wrapped.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_counts_characters() {
+        assert_eq!(truncate("hello", 5), "hello");
+        assert_eq!(truncate("hello world", 5), "hello...");
+        assert_eq!(truncate("héllo wörld", 7), "héllo w...");
+        assert_eq!(truncate("日本語のパッケージ", 3), "日本語...");
     }
 }